@@ -0,0 +1,107 @@
+// Packet parsing and building, and acks against growing resend backlogs
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use matchmaker::packets::{build_server_packet, parse_client_packet, ClientPacket, JoinReply, PacketShipper, ServerPacket, Wire};
+use matchmaker::runtime::{SystemClock, Transport};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const BACKLOGS: [usize; 3] = [100, 10_000, 100_000];
+
+// Datagrams go nowhere
+struct Discard;
+
+impl Transport for Discard {
+    fn send_to(&self, data: &[u8], _: SocketAddr) -> std::io::Result<usize> {
+        Ok(data.len())
+    }
+}
+
+fn datagram(id: u32, packet: &ClientPacket) -> Vec<u8> {
+    let mut data = id.to_le_bytes().to_vec();
+    packet.encode(&mut data);
+    data
+}
+
+fn parse(c: &mut Criterion) {
+    let packets = [
+        ("ack", ClientPacket::Ack { id: 42 }),
+        ("create", ClientPacket::Create { client_hash: String::from("0123456789abcdef"), password_protected: true }),
+        ("join", ClientPacket::Join { client_hash: String::from("0123456789abcdef"), session_key: String::from("AbCdEfG") })
+    ];
+
+    let mut group = c.benchmark_group("parse_client_packet");
+
+    for (name, packet) in &packets {
+        let data = datagram(7, packet);
+        group.bench_with_input(BenchmarkId::from_parameter(name), &data, |b, data| {
+            b.iter(|| parse_client_packet(black_box(data)))
+        });
+    }
+
+    group.finish();
+}
+
+fn build(c: &mut Criterion) {
+    let address: SocketAddr = "203.0.113.7:4000".parse().unwrap();
+    let ticket = [7; 120];
+    let match_key = [9; 32];
+
+    let packets = [
+        ("ack", ServerPacket::Ack { id: 42 }),
+        ("create", ServerPacket::Create { session_key: "AbCdEfG" }),
+        ("join", ServerPacket::Join(Some(JoinReply { client_addr: address, ticket: &ticket, match_key: &match_key })))
+    ];
+
+    let mut group = c.benchmark_group("build_server_packet");
+
+    for (name, packet) in &packets {
+        group.bench_with_input(BenchmarkId::from_parameter(name), packet, |b, packet| {
+            b.iter(|| build_server_packet(black_box(packet)))
+        });
+    }
+
+    group.finish();
+}
+
+// Acks the packet in the middle of the backlog, then sends another so the
+// backlog keeps its size
+fn acknowledge(c: &mut Criterion) {
+    let address: SocketAddr = "203.0.113.7:4000".parse().unwrap();
+    let mut group = c.benchmark_group("acknowledge");
+
+    for backlog in BACKLOGS.iter() {
+        group.bench_with_input(BenchmarkId::from_parameter(backlog), backlog, |b, &backlog| {
+            let mut shipper = PacketShipper::new(address, Duration::from_secs(1), Arc::new(SystemClock));
+
+            for _ in 0..backlog {
+                shipper.send(&Discard, &ServerPacket::Ping);
+            }
+
+            let mut next_id = backlog as u32;
+
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::default();
+
+                for _ in 0..iters {
+                    let id = next_id - backlog as u32 / 2;
+
+                    let start = Instant::now();
+                    shipper.acknowledge(black_box(id));
+                    elapsed += start.elapsed();
+
+                    shipper.send(&Discard, &ServerPacket::Ping);
+                    next_id += 1;
+                }
+
+                elapsed
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, parse, build, acknowledge);
+criterion_main!(benches);
@@ -0,0 +1,168 @@
+// The session paths of a server holding 10k to 100k sessions, driven through
+// `Server::handle_message` with replies thrown away
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use matchmaker::config::ServerConfig;
+use matchmaker::packets::ClientPacket;
+use matchmaker::runtime::Transport;
+use matchmaker::server::Server;
+use matchmaker::threads::ThreadMessage;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+const SESSIONS: [usize; 2] = [10_000, 100_000];
+
+const CLIENT_HASH: &str = "bench-build";
+
+// A new client is sent a challenge and its resume token first
+const FIRST_REPLY_ID: u32 = 2;
+
+struct Discard;
+
+impl Transport for Discard {
+    fn send_to(&self, data: &[u8], _: SocketAddr) -> std::io::Result<usize> {
+        Ok(data.len())
+    }
+}
+
+fn address(index: usize) -> SocketAddr {
+    SocketAddr::from(([10, (index >> 16) as u8, (index >> 8) as u8, index as u8], 5000))
+}
+
+struct Bench {
+    server: Server,
+    // next packet id for each client, and the id of the server's next reply
+    ids: HashMap<SocketAddr, (u32, u32)>
+}
+
+impl Bench {
+    // A server with `sessions` hosts waiting
+    fn new(sessions: usize, password_protected: bool) -> Bench {
+        let config = ServerConfig {
+            legacy_hashes: true,
+            snapshot_interval: 0.0,
+            ..ServerConfig::default()
+        };
+
+        let mut server = Server::new(config);
+        server.support_client_hashes(vec![String::from(CLIENT_HASH)]);
+
+        let mut bench = Bench { server, ids: HashMap::new() };
+
+        for index in 0..sessions {
+            bench.send(address(index), ClientPacket::Create { client_hash: String::from(CLIENT_HASH), password_protected });
+        }
+
+        bench
+    }
+
+    // Returns the id of the server's reply, if the packet gets one
+    fn send(&mut self, from: SocketAddr, packet: ClientPacket) -> u32 {
+        let ids = self.ids.entry(from).or_insert((0, FIRST_REPLY_ID));
+        let (id, reply_id) = *ids;
+        ids.0 += 1;
+
+        if !matches!(packet, ClientPacket::Ack { .. } | ClientPacket::Close) {
+            ids.1 += 1;
+        }
+
+        self.server.handle_message(&Discard, ThreadMessage::ClientPacket { socket_address: from, id, packet });
+
+        reply_id
+    }
+}
+
+// One more host creates a session, then closes it again untimed
+fn create_session(c: &mut Criterion) {
+    let mut group = c.benchmark_group("create_session");
+    group.sample_size(20);
+
+    for sessions in SESSIONS.iter() {
+        let mut bench = Bench::new(*sessions, false);
+        let host = address(*sessions);
+
+        group.bench_with_input(BenchmarkId::from_parameter(sessions), sessions, |b, _| {
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::default();
+
+                for _ in 0..iters {
+                    let start = Instant::now();
+                    let reply_id = bench.send(host, ClientPacket::Create { client_hash: String::from(CLIENT_HASH), password_protected: false });
+                    elapsed += start.elapsed();
+
+                    bench.send(host, ClientPacket::Ack { id: reply_id });
+                    bench.send(host, ClientPacket::Close);
+                }
+
+                elapsed
+            });
+        });
+    }
+
+    group.finish();
+}
+
+// Every session is private, so a public join looks at all of them and fails
+fn join_open_session(c: &mut Criterion) {
+    let mut group = c.benchmark_group("join_open_session_miss");
+    group.sample_size(20);
+
+    for sessions in SESSIONS.iter() {
+        let mut bench = Bench::new(*sessions, true);
+        let joiner = address(*sessions);
+
+        let join = || ClientPacket::Join { client_hash: String::from(CLIENT_HASH), session_key: String::new() };
+
+        group.bench_with_input(BenchmarkId::from_parameter(sessions), sessions, |b, _| {
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::default();
+
+                for _ in 0..iters {
+                    let start = Instant::now();
+                    let reply_id = bench.send(joiner, join());
+                    elapsed += start.elapsed();
+
+                    bench.send(joiner, ClientPacket::Ack { id: reply_id });
+                }
+
+                elapsed
+            });
+        });
+    }
+
+    group.finish();
+}
+
+// Keys are looked up directly, so a join by key shouldn't depend on the session count
+fn join_by_key(c: &mut Criterion) {
+    let mut group = c.benchmark_group("join_by_key");
+    group.sample_size(20);
+
+    for sessions in SESSIONS.iter() {
+        let mut bench = Bench::new(*sessions, true);
+        let joiner = address(*sessions);
+
+        group.bench_with_input(BenchmarkId::from_parameter(sessions), sessions, |b, _| {
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::default();
+
+                for _ in 0..iters {
+                    // keys are random, so join one that doesn't exist
+                    let start = Instant::now();
+                    let reply_id = bench.send(joiner, ClientPacket::Join { client_hash: String::from(CLIENT_HASH), session_key: String::from("missing") });
+                    elapsed += start.elapsed();
+
+                    bench.send(joiner, ClientPacket::Ack { id: reply_id });
+                }
+
+                elapsed
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, create_session, join_open_session, join_by_key);
+criterion_main!(benches);
@@ -0,0 +1,22 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use matchmaker::packets::{parse_client_packet, read_bytes_u8, read_string_u8, read_u16, read_u32};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_client_packet(data);
+
+    // the readers must never read past the end or panic on bad utf-8
+    let mut buf = data;
+
+    while !buf.is_empty() {
+        let before = buf.len();
+
+        let _ = read_string_u8(&mut buf);
+        let _ = read_bytes_u8(&mut buf);
+        let _ = read_u16(&mut buf);
+        let _ = read_u32(&mut buf);
+
+        assert!(buf.len() < before);
+    }
+});
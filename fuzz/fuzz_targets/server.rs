@@ -0,0 +1,48 @@
+#![no_main]
+
+// Feeds datagrams from a handful of addresses to a simulated server, stepping
+// the clock in between, and checks its state after every message
+
+use libfuzzer_sys::fuzz_target;
+use matchmaker::config::ServerConfig;
+use matchmaker::simulation::{NetworkConditions, Simulation};
+use std::net::SocketAddr;
+
+const CLIENT_HASH: &str = "fuzz-build";
+
+fuzz_target!(|data: &[u8]| {
+    let config = ServerConfig {
+        legacy_hashes: true,
+        snapshot_interval: 0.0,
+        key_length: 1,
+        ..ServerConfig::default()
+    };
+
+    let mut simulation = Simulation::new(config, 0, NetworkConditions::default());
+    simulation.server().support_client_hashes(vec![String::from(CLIENT_HASH)]);
+
+    // `[op u8][len u8][datagram]...`, the low bits of op pick the sender and
+    // the high bit steps the clock instead of sending
+    let mut buf = data;
+
+    while buf.len() >= 2 {
+        let op = buf[0];
+        let len = (buf[1] as usize).min(buf.len() - 2);
+        let datagram = &buf[2..2 + len];
+        buf = &buf[2 + len..];
+
+        if op & 0x80 != 0 {
+            for _ in 0..(op & 0x7F) {
+                simulation.step();
+            }
+        } else {
+            let from = SocketAddr::from(([10, 0, 1, op & 0x03], 5000));
+            simulation.send(from, datagram);
+            simulation.step();
+        }
+
+        if let Err(e) = simulation.check_invariants() {
+            panic!("{}", e);
+        }
+    }
+});
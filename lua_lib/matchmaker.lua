@@ -9,15 +9,22 @@ local lib = {
     socket = nil,              -- udp socket
     session_key = "",          -- active session key (host only)
     remote_addr = "",          -- remote connection
+    match_ticket = "",         -- signed ticket proving the match to the remote
+    match_key = "",            -- 32 byte key shared with the remote for this match
     client_hash = "",          -- crypto hash of client to verify authenticity
     sent_packets = {},         -- list of unack'd packaget that had been sent
     errors = {},               -- list of errors
+    error_codes = {},          -- ErrorCode for each entry in errors
+    notices = {},              -- messages from the server not read yet
     next_packet_id = 0,        -- our next packet ID
     server_next_packet_id = 0, -- track the next packet from the server
     max_packet_len = 512,      -- max packet len a socket can read
     debug = false,             -- Prints debug information to console
     is_joining = false,       -- indicates whether we were trying to join
-    join_status = ""           -- indicates if the last join failed
+    join_status = "",          -- indicates if the last join failed
+    close_reason = "",         -- why the server closed our connection, if it did
+    resume_token = "",         -- lets us keep our connection after our address changes
+    expired_session = ""       -- key of our last session closed by the server for being open too long
 }
 
 --[[
@@ -30,7 +37,24 @@ local PacketHeader = {
     Create = 2,
     Join = 3 ,
     Close = 4,
-    Error = 5    
+    Error = 5,
+    Notice = 9,
+    Resume = 10,
+    SessionExpired = 11
+}
+
+--[[
+Sent with every Error packet
+--]]
+lib.ErrorCode = {
+    Unknown = 0,
+    NotAttested = 1,
+    AttestationFailed = 2,
+    KeyExchangeFailed = 3,
+    SessionFailed = 4,
+    ShuttingDown = 5,
+    Maintenance = 6,
+    ResumeFailed = 7
 }
 
 --[[
@@ -82,6 +106,12 @@ local function send_packet(ctx, packet_id, header, data)
         end
     end
 
+    -- { token: bytes }
+    if header == PacketHeader.Resume then 
+        ctx:_debug_print("Sending Resume Packet")
+        serializer:write_string(data.token, littleEndian)
+    end
+
     --[[
     Packets PingPong and Close only consist of the header 
     --]]
@@ -145,13 +175,47 @@ local function read_packet(ctx, bytestream)
     end
 
 
-    -- { id: u32, message: str }
+    -- { id: u32, message: str, code: u16 }
     if header == PacketHeader.Error then 
         local id = serializer:read_u32(littleEndian)
         local message = serializer:read_string()
+        local code = serializer:read_u16(littleEndian)
         ctx:_debug_print("Error packet recieved: "..message)
         ctx.sent_packets[id] = nil
         ctx.errors[#ctx.errors+1] = message
+        ctx.error_codes[#ctx.error_codes+1] = code
+    end
+
+    -- { token: bytes }
+    if header == PacketHeader.Resume then 
+        ctx:_debug_print("Resume token recieved")
+        ctx.resume_token = serializer:read_string()
+    end
+
+    -- { session_key: str }
+    if header == PacketHeader.SessionExpired then 
+        local session_key = serializer:read_string()
+        ctx:_debug_print("SessionExpired packet recieved: "..session_key)
+        ctx.expired_session = session_key
+
+        if ctx.session_key == session_key then
+            ctx.session_key = ""
+        end
+    end
+
+    -- { message: str }
+    if header == PacketHeader.Notice then 
+        local message = serializer:read_string()
+        ctx:_debug_print("Notice packet recieved: "..message)
+        ctx.notices[#ctx.notices+1] = message
+    end
+
+    -- { reason: str }
+    if header == PacketHeader.Close then 
+        local reason = serializer:read_string()
+        ctx:_debug_print("Close packet recieved: "..reason)
+        ctx.close_reason = reason
+        ctx.session_key = ""
     end
 
     -- { session_key: str }
@@ -161,7 +225,7 @@ local function read_packet(ctx, bytestream)
         ctx.session_key = session_key
     end
 
-    -- { success: bool, socket_address: str }
+    -- { success: bool, socket_address: str, ticket: bytes, match_key: bytes }
     if header == PacketHeader.Join and ctx.is_joining then 
         ctx:_debug_print("Join response package recieved")
         local success = serializer:read_u8()
@@ -169,6 +233,8 @@ local function read_packet(ctx, bytestream)
         if success == 1 then 
             local socket_address = serializer:read_string()
             ctx.remote_addr = socket_address
+            ctx.match_ticket = serializer:read_string()
+            ctx.match_key = serializer:read_string()
             ctx.join_status = "success"
         else 
             ctx.join_status = "failed"
@@ -202,12 +268,19 @@ function lib:init(client_hash, ip, port, timeout, debug)
     self.client_hash = client_hash
     self.session_key = ""
     self.remote_addr = ""
+    self.match_ticket = ""
+    self.match_key = ""
     self.sent_packets = {}
     self.errors = {}
+    self.error_codes = {}
+    self.notices = {}
     self.next_packet_id = 0
     self.server_next_packet_id = 0
     self.is_joining = false 
     self.join_status = "" 
+    self.close_reason = ""
+    self.resume_token = ""
+    self.expired_session = ""
 
     if timeout ~= nil then
         self.timeout = timeout
@@ -234,6 +307,27 @@ function lib:init(client_hash, ip, port, timeout, debug)
     end
 end
 
+-- Opens a new socket and moves our connection, session and unacknowledged
+-- packets over to it, for example after switching networks
+function lib:resume()
+    if string.len(self.resume_token) == 0 then
+        self:_debug_print("No resume token, request supressed")
+        return
+    end
+
+    if self.socket then 
+        self.socket:close()
+    end 
+
+    self.socket = socket.udp()
+    self.socket:setoption('reuseaddr',true)
+    self.socket:setsockname('*', 0)
+    self.socket:setpeername(self.ip, self.port)
+    self.socket:settimeout(self.timeout)
+
+    send_packet(self, self.next_packet_id, PacketHeader.Resume, { token = self.resume_token })
+end
+
 function lib:create_session(password_protected)
     if self:check_config() then
         if self.is_joining then 
@@ -327,10 +421,41 @@ function lib:get_session()
     return self.session_key
 end
 
+-- ErrorCode of the latest error, nil if there were none
+function lib:get_last_error_code()
+    return self.error_codes[#self.error_codes]
+end
+
+-- Oldest unread notice from the server, nil if there are none
+function lib:pop_notice()
+    return table.remove(self.notices, 1)
+end
+
+-- Key of our last session that the server closed for being open too long
+function lib:get_expired_session()
+    return self.expired_session
+end
+
+-- Non-empty once the server has closed our connection
+function lib:get_close_reason()
+    return self.close_reason
+end
+
 function lib:get_remote_addr()
     return self.remote_addr
 end
 
+-- Send this to the remote so it can verify the match
+-- against the server's public key
+function lib:get_match_ticket()
+    return self.match_ticket
+end
+
+-- Symmetric key for encrypting traffic with the remote
+function lib:get_match_key()
+    return self.match_key
+end
+
 function lib:sleep(seconds)
     socket.sleep(seconds)
 end
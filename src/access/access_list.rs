@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// A single address or a CIDR block such as `10.0.0.0/8`
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix: u8
+}
+
+impl IpRange {
+    // IPv4 peers of a dual-stack socket arrive as `::ffff:a.b.c.d`
+    // and are matched as the IPv4 address they are
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = mask_u32(self.prefix);
+                u32::from(network) & mask == u32::from(ip) & mask
+            },
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = mask_u128(self.prefix);
+                u128::from(network) & mask == u128::from(ip) & mask
+            },
+            _ => false
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<IpRange, String> {
+        let (address, prefix) = match s.find('/') {
+            Some(position) => (&s[..position], Some(&s[position + 1..])),
+            None => (s, None)
+        };
+
+        let network = address
+            .parse::<IpAddr>()
+            .map_err(|_| format!("`{}` is not an ip address", address))?;
+
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(prefix) => match prefix.parse::<u8>() {
+                Ok(x) if x <= max_prefix => x,
+                _ => return Err(format!("`{}` is not a valid prefix length", prefix))
+            },
+            None => max_prefix
+        };
+
+        // `::ffff:10.0.0.0/104` is the same range as `10.0.0.0/8`
+        let (network, prefix) = match network.to_canonical() {
+            IpAddr::V4(mapped) if network.is_ipv6() && prefix >= 96 => (IpAddr::V4(mapped), prefix - 96),
+            _ => (network, prefix)
+        };
+
+        // `10.0.0.1/8` is kept as `10.0.0.0/8`, so the same range always compares equal
+        let network = match network {
+            IpAddr::V4(network) => IpAddr::V4((u32::from(network) & mask_u32(prefix)).into()),
+            IpAddr::V6(network) => IpAddr::V6((u128::from(network) & mask_u128(prefix)).into())
+        };
+
+        Ok(IpRange { network, prefix })
+    }
+}
+
+impl std::fmt::Display for IpRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+fn mask_u32(prefix: u8) -> u32 {
+    if prefix == 0 { 0 } else { u32::MAX << (32 - prefix as u32) }
+}
+
+fn mask_u128(prefix: u8) -> u128 {
+    if prefix == 0 { 0 } else { u128::MAX << (128 - prefix as u32) }
+}
+
+struct Ban {
+    range: IpRange,
+    expires: Option<Instant>
+}
+
+impl Ban {
+    fn is_expired(&self, now: Instant) -> bool {
+        matches!(self.expires, Some(expires) if expires <= now)
+    }
+}
+
+// Bans reject matching addresses. If any allow entries exist, only
+// addresses matching one of them are accepted at all.
+//
+// Entries loaded from the file are replaced on every reload, while entries
+// added at runtime are kept until they are removed or expire. A temporary
+// file ban counts from when its line was first loaded, so reloading neither
+// extends it nor brings it back once it has run out.
+#[derive(Default)]
+pub struct AccessList {
+    file_bans: Vec<Ban>,
+    file_allows: Vec<IpRange>,
+    // expiry of every `ban <range> <seconds>` line in the file, expired or not
+    file_ban_expiry: HashMap<(IpRange, u64), Instant>,
+    runtime_bans: Vec<Ban>,
+    runtime_allows: Vec<IpRange>
+}
+
+impl AccessList {
+    pub fn new() -> AccessList {
+        AccessList::default()
+    }
+
+    // Reads lines in the form `ban <range> [seconds]` or `allow <range>`.
+    // Blank lines and lines starting with `#` are ignored.
+    pub fn load(&mut self, path: &str, now: Instant) -> Result<(), String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        let reader = BufReader::new(file);
+
+        let mut bans = Vec::new();
+        let mut allows = Vec::new();
+        let mut expiry = HashMap::new();
+
+        for (number, line) in reader.lines().enumerate() {
+            let line = line.map_err(|e| format!("{}: {}", path, e))?;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+
+            let result = match words.as_slice() {
+                ["ban", range] => range.parse().map(|range| bans.push(Ban { range, expires: None })),
+                ["ban", range, seconds] => match seconds.parse::<u64>() {
+                    Ok(seconds) => range.parse().map(|range| {
+                        let expires = self.file_ban_expiry
+                            .get(&(range, seconds))
+                            .cloned()
+                            .unwrap_or_else(|| now + Duration::from_secs(seconds));
+
+                        expiry.insert((range, seconds), expires);
+                        bans.push(Ban { range, expires: Some(expires) });
+                    }),
+                    Err(_) => Err(format!("`{}` is not a number of seconds", seconds))
+                },
+                ["allow", range] => range.parse().map(|range| allows.push(range)),
+                _ => Err(format!("unknown entry `{}`", line))
+            };
+
+            if let Err(e) = result {
+                return Err(format!("{}:{}: {}", path, number + 1, e));
+            }
+        }
+
+        bans.retain(|ban| !ban.is_expired(now));
+
+        self.file_bans = bans;
+        self.file_allows = allows;
+        self.file_ban_expiry = expiry;
+
+        Ok(())
+    }
+
+    pub fn permits(&self, ip: &IpAddr, now: Instant) -> bool {
+        let banned = self.file_bans.iter()
+            .chain(self.runtime_bans.iter())
+            .any(|ban| !ban.is_expired(now) && ban.range.contains(ip));
+
+        if banned {
+            return false;
+        }
+
+        if self.file_allows.is_empty() && self.runtime_allows.is_empty() {
+            return true;
+        }
+
+        self.file_allows.iter()
+            .chain(self.runtime_allows.iter())
+            .any(|range| range.contains(ip))
+    }
+
+    pub fn ban(&mut self, range: IpRange, duration: Option<Duration>, now: Instant) {
+        self.runtime_bans.retain(|ban| ban.range != range);
+        self.runtime_bans.push(Ban {
+            range,
+            expires: duration.map(|duration| now + duration)
+        });
+    }
+
+    // Removes the ban from both the file and runtime entries,
+    // returns false if nothing matched
+    pub fn unban(&mut self, range: &IpRange) -> bool {
+        let count = self.file_bans.len() + self.runtime_bans.len();
+
+        self.file_bans.retain(|ban| ban.range != *range);
+        self.runtime_bans.retain(|ban| ban.range != *range);
+
+        count != self.file_bans.len() + self.runtime_bans.len()
+    }
+
+    pub fn allow(&mut self, range: IpRange) {
+        if !self.runtime_allows.contains(&range) {
+            self.runtime_allows.push(range);
+        }
+    }
+
+    // Removes the allow entry from both the file and runtime entries,
+    // returns false if nothing matched
+    pub fn disallow(&mut self, range: &IpRange) -> bool {
+        let count = self.file_allows.len() + self.runtime_allows.len();
+
+        self.file_allows.retain(|allowed| allowed != range);
+        self.runtime_allows.retain(|allowed| allowed != range);
+
+        count != self.file_allows.len() + self.runtime_allows.len()
+    }
+
+    // Drops temporary bans that have run out
+    pub fn remove_expired(&mut self, now: Instant) {
+        self.file_bans.retain(|ban| !ban.is_expired(now));
+        self.runtime_bans.retain(|ban| !ban.is_expired(now));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn range(s: &str) -> IpRange {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn access_file(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("matchmaker-access-{}-{}.txt", name, std::process::id()));
+        File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn prefix_zero_matches_every_address_of_its_family() {
+        assert!(range("0.0.0.0/0").contains(&ip("203.0.113.9")));
+        assert!(range("::/0").contains(&ip("2001:db8::1")));
+        assert!(!range("0.0.0.0/0").contains(&ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn full_prefix_matches_one_address() {
+        assert_eq!(range("10.0.0.1"), range("10.0.0.1/32"));
+        assert!(range("10.0.0.1/32").contains(&ip("10.0.0.1")));
+        assert!(!range("10.0.0.1/32").contains(&ip("10.0.0.2")));
+
+        assert_eq!(range("2001:db8::1"), range("2001:db8::1/128"));
+        assert!(range("2001:db8::1/128").contains(&ip("2001:db8::1")));
+        assert!(!range("2001:db8::1/128").contains(&ip("2001:db8::2")));
+    }
+
+    #[test]
+    fn blocks_match_by_prefix() {
+        assert!(range("10.0.0.0/8").contains(&ip("10.255.1.2")));
+        assert!(!range("10.0.0.0/8").contains(&ip("11.0.0.1")));
+        assert!(range("2001:db8::/32").contains(&ip("2001:db8:ffff::1")));
+        assert!(!range("2001:db8::/32").contains(&ip("2001:db9::1")));
+    }
+
+    #[test]
+    fn bad_ranges_are_refused() {
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("2001:db8::/129".parse::<IpRange>().is_err());
+        assert!("10.0.0.0/x".parse::<IpRange>().is_err());
+        assert!("10.0.0.0/".parse::<IpRange>().is_err());
+        assert!("10.0.0/8".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn families_do_not_match_each_other() {
+        assert!(!range("10.0.0.0/8").contains(&ip("2001:db8::1")));
+        assert!(!range("2001:db8::/32").contains(&ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn mapped_addresses_match_ipv4_ranges() {
+        assert!(range("127.0.0.0/8").contains(&ip("::ffff:127.0.0.1")));
+        assert!(!range("10.0.0.0/8").contains(&ip("::ffff:127.0.0.1")));
+
+        // a mapped range is the ipv4 range it maps
+        assert_eq!(range("::ffff:10.0.0.0/104"), range("10.0.0.0/8"));
+        assert!(range("::ffff:10.0.0.1").contains(&ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn host_bits_are_cleared_so_a_block_is_unbanned_however_it_was_written() {
+        assert_eq!(range("10.0.0.1/8"), range("10.0.0.0/8"));
+        assert_eq!(range("10.0.0.1/8").to_string(), "10.0.0.0/8");
+        assert_eq!(range("2001:db8::1/32"), range("2001:db8::/32"));
+        assert_eq!(range("::ffff:10.1.2.3/104"), range("10.0.0.0/8"));
+
+        let now = Instant::now();
+        let mut access_list = AccessList::new();
+
+        access_list.ban(range("10.0.0.1/8"), None, now);
+        assert!(!access_list.permits(&ip("10.2.3.4"), now));
+
+        assert!(access_list.unban(&range("10.0.0.0/8")));
+        assert!(access_list.permits(&ip("10.2.3.4"), now));
+    }
+
+    #[test]
+    fn mapped_peers_are_banned_and_allowed_by_ipv4_entries() {
+        let now = Instant::now();
+        let mut access_list = AccessList::new();
+
+        access_list.allow(range("192.0.2.0/24"));
+        access_list.ban(range("192.0.2.7"), None, now);
+
+        assert!(access_list.permits(&ip("::ffff:192.0.2.1"), now));
+        assert!(!access_list.permits(&ip("::ffff:192.0.2.7"), now));
+        assert!(!access_list.permits(&ip("::ffff:198.51.100.1"), now));
+    }
+
+    #[test]
+    fn reloading_keeps_when_a_temporary_ban_runs_out() {
+        let path = access_file("reload", "ban 10.0.0.1 60\nban 10.0.0.2\n");
+        let start = Instant::now();
+        let mut access_list = AccessList::new();
+
+        access_list.load(&path, start).unwrap();
+
+        // not extended by a reload part way through
+        access_list.load(&path, start + Duration::from_secs(30)).unwrap();
+        assert!(access_list.permits(&ip("10.0.0.1"), start + Duration::from_secs(61)));
+
+        // and not brought back once it was dropped
+        access_list.remove_expired(start + Duration::from_secs(61));
+        access_list.load(&path, start + Duration::from_secs(62)).unwrap();
+        assert!(access_list.permits(&ip("10.0.0.1"), start + Duration::from_secs(62)));
+        assert!(!access_list.permits(&ip("10.0.0.2"), start + Duration::from_secs(62)));
+
+        // a changed duration is a new entry
+        File::create(&path).unwrap().write_all(b"ban 10.0.0.1 120\n").unwrap();
+        access_list.load(&path, start + Duration::from_secs(63)).unwrap();
+        assert!(!access_list.permits(&ip("10.0.0.1"), start + Duration::from_secs(63)));
+        assert!(access_list.permits(&ip("10.0.0.1"), start + Duration::from_secs(184)));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
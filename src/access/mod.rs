@@ -0,0 +1,2 @@
+mod access_list;
+pub use access_list::{AccessList, IpRange};
@@ -0,0 +1,170 @@
+use crate::encoding::decode_hex;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+pub const NONCE_LEN: usize = 16;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub fn generate_nonce() -> [u8; NONCE_LEN] {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill(&mut nonce);
+    nonce
+}
+
+// Each game build is given its own secret. A client proves which build it
+// is by answering the server's nonce with HMAC-SHA256(build_secret, nonce)
+// so the secret itself never crosses the wire.
+#[derive(Default)]
+pub struct BuildSecrets {
+    secrets: HashMap<String, Vec<u8>>
+}
+
+impl BuildSecrets {
+    pub fn new() -> BuildSecrets {
+        BuildSecrets::default()
+    }
+
+    // Reads lines in the form `<build id> <hex encoded secret>`.
+    // Blank lines and lines starting with `#` are ignored.
+    pub fn load(path: &str) -> Result<BuildSecrets, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        let reader = BufReader::new(file);
+        let mut secrets = HashMap::new();
+
+        for (number, line) in reader.lines().enumerate() {
+            let line = line.map_err(|e| format!("{}: {}", path, e))?;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+
+            match words.as_slice() {
+                [build, _] if secrets.contains_key(*build) => {
+                    return Err(format!("{}:{}: build `{}` is listed twice", path, number + 1, build))
+                },
+                [build, secret] => match decode_hex(secret) {
+                    Some(secret) if !secret.is_empty() => {
+                        secrets.insert(build.to_string(), secret);
+                    },
+                    _ => return Err(format!("{}:{}: secret must be non-empty hex", path, number + 1))
+                },
+                _ => return Err(format!("{}:{}: expected `<build> <secret>`", path, number + 1))
+            }
+        }
+
+        Ok(BuildSecrets { secrets })
+    }
+
+    pub fn len(&self) -> usize {
+        self.secrets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.secrets.is_empty()
+    }
+
+    pub fn verify(&self, build: &str, nonce: &[u8], mac: &[u8]) -> bool {
+        let secret = match self.secrets.get(build) {
+            Some(secret) => secret,
+            None => return false
+        };
+
+        let mut hmac = match HmacSha256::new_from_slice(secret) {
+            Ok(hmac) => hmac,
+            Err(_) => return false
+        };
+
+        hmac.update(nonce);
+
+        // constant time comparison
+        hmac.verify_slice(mac).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn load(name: &str, contents: &str) -> Result<BuildSecrets, String> {
+        let path = std::env::temp_dir().join(format!("matchmaker-secrets-{}-{}.txt", name, std::process::id()));
+        File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+
+        let result = BuildSecrets::load(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    fn mac(secret: &[u8], nonce: &[u8]) -> Vec<u8> {
+        let mut hmac = HmacSha256::new_from_slice(secret).unwrap();
+        hmac.update(nonce);
+        hmac.finalize().into_bytes().to_vec()
+    }
+
+    #[test]
+    fn secrets_are_read_with_comments_and_blank_lines() {
+        let secrets = load("valid", "# builds\n\nwin-1.0 00ff10\nmac-1.0 abcdef\n").unwrap();
+        assert_eq!(secrets.len(), 2);
+    }
+
+    #[test]
+    fn bad_hex_is_refused() {
+        let error = load("bad-hex", "win-1.0 zz11\n").err().unwrap();
+        assert!(error.ends_with(":1: secret must be non-empty hex"), "{}", error);
+    }
+
+    #[test]
+    fn odd_length_hex_is_refused() {
+        assert!(load("odd", "win-1.0 abc\n").is_err());
+    }
+
+    #[test]
+    fn a_build_without_a_secret_is_refused() {
+        let error = load("empty", "# builds\nwin-1.0\n").err().unwrap();
+        assert!(error.ends_with(":2: expected `<build> <secret>`"), "{}", error);
+    }
+
+    #[test]
+    fn a_build_listed_twice_is_refused() {
+        let error = load("duplicate", "win-1.0 00ff\nwin-1.0 11ee\n").err().unwrap();
+        assert!(error.ends_with(":2: build `win-1.0` is listed twice"), "{}", error);
+    }
+
+    #[test]
+    fn the_hmac_of_the_nonce_is_accepted() {
+        let secrets = load("verify", "win-1.0 00ff10\n").unwrap();
+        let nonce = generate_nonce();
+
+        assert!(secrets.verify("win-1.0", &nonce, &mac(&[0x00, 0xff, 0x10], &nonce)));
+    }
+
+    #[test]
+    fn a_wrong_mac_is_refused() {
+        let secrets = load("wrong-mac", "win-1.0 00ff10\n").unwrap();
+        let nonce = generate_nonce();
+
+        let mut wrong = mac(&[0x00, 0xff, 0x10], &nonce);
+        wrong[0] ^= 1;
+
+        assert!(!secrets.verify("win-1.0", &nonce, &wrong));
+        assert!(!secrets.verify("win-1.0", &nonce, &mac(&[0x00, 0xff, 0x11], &nonce)));
+        assert!(!secrets.verify("win-1.0", &generate_nonce(), &mac(&[0x00, 0xff, 0x10], &nonce)));
+        assert!(!secrets.verify("win-1.0", &nonce, &[]));
+    }
+
+    #[test]
+    fn an_unknown_build_is_refused() {
+        let secrets = load("unknown", "win-1.0 00ff10\n").unwrap();
+        let nonce = generate_nonce();
+
+        assert!(!secrets.verify("mac-1.0", &nonce, &mac(&[0x00, 0xff, 0x10], &nonce)));
+    }
+}
@@ -0,0 +1,2 @@
+mod build_secrets;
+pub use build_secrets::{BuildSecrets, generate_nonce, NONCE_LEN};
\ No newline at end of file
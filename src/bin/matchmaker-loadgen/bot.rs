@@ -0,0 +1,258 @@
+use crate::report::Stats;
+use matchmaker::packets::{ClientPacket, ServerPacket, Wire};
+use rand::prng::XorShiftRng;
+use rand::Rng;
+use std::collections::{HashSet, VecDeque};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// How long to wait for an ack before sending again
+const RESEND_DELAY: Duration = Duration::from_millis(200);
+
+// Pause between one request finishing and the next starting
+const THINK_TIME: Duration = Duration::from_millis(100);
+
+// Pause after an error or failed join
+const BACKOFF: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy, Debug)]
+pub enum Role {
+    Host,
+    Join,
+    Private,
+    Close
+}
+
+enum State {
+    Idle { until: Instant },
+    Creating { sent: Instant },
+    Hosting { created: Instant, close_at: Option<Instant> },
+    Joining { sent: Instant }
+}
+
+// Keys of waiting private sessions, shared by every bot
+pub type PrivateKeys = Arc<Mutex<VecDeque<String>>>;
+
+// A client that talks to the server like a real one would: it acks every
+// data packet, answers pings and resends its own packets until they're acked.
+// It keeps one packet in flight since the server ignores older ids.
+pub struct Bot {
+    socket: UdpSocket,
+    server: SocketAddr,
+    role: Role,
+    hash: String,
+    loss: f64,
+    state: State,
+    next_id: u32,
+    queued: VecDeque<ClientPacket>,
+    in_flight: Option<InFlight>,
+    seen: HashSet<u32>
+}
+
+struct InFlight {
+    id: u32,
+    data: Vec<u8>,
+    last_sent: Option<Instant>
+}
+
+impl Bot {
+    pub fn new(server: SocketAddr, role: Role, hash: &str, loss: f64, start_at: Instant) -> std::io::Result<Bot> {
+        let bind_address = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(bind_address)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(Bot {
+            socket,
+            server,
+            role,
+            hash: hash.to_string(),
+            loss,
+            state: State::Idle { until: start_at },
+            next_id: 0,
+            queued: VecDeque::new(),
+            in_flight: None,
+            seen: HashSet::new()
+        })
+    }
+
+    // Handles what arrived and sends what's due, returns true if anything happened
+    pub fn update(&mut self, now: Instant, rng: &mut XorShiftRng, private_keys: &PrivateKeys, stats: &mut Stats) -> bool {
+        let mut active = self.receive(now, rng, private_keys, stats);
+
+        match self.state {
+            State::Idle { until } if now >= until => {
+                self.start(now, private_keys, stats);
+                active = true;
+            },
+            State::Hosting { close_at: Some(close_at), .. } if now >= close_at => {
+                self.queued.push_back(ClientPacket::Close);
+                self.state = State::Idle { until: now + THINK_TIME };
+                stats.closes += 1;
+                active = true;
+            },
+            _ => {}
+        }
+
+        self.flush(now, rng, stats) || active
+    }
+
+    fn start(&mut self, now: Instant, private_keys: &PrivateKeys, stats: &mut Stats) {
+        let client_hash = self.hash.clone();
+
+        match self.role {
+            Role::Host | Role::Close | Role::Private => {
+                let password_protected = matches!(self.role, Role::Private);
+                self.queued.push_back(ClientPacket::Create { client_hash, password_protected });
+                self.state = State::Creating { sent: now };
+                stats.creates += 1;
+            },
+            Role::Join => {
+                let session_key = private_keys.lock().unwrap().pop_front().unwrap_or_default();
+                self.queued.push_back(ClientPacket::Join { client_hash, session_key });
+                self.state = State::Joining { sent: now };
+                stats.joins += 1;
+            }
+        }
+    }
+
+    fn receive(&mut self, now: Instant, rng: &mut XorShiftRng, private_keys: &PrivateKeys, stats: &mut Stats) -> bool {
+        let mut buf = [0; 1024];
+        let mut active = false;
+
+        while let Ok(len) = self.socket.recv(&mut buf) {
+            active = true;
+
+            if rng.gen_bool(self.loss) {
+                stats.lost += 1;
+                continue;
+            }
+
+            stats.received += 1;
+
+            match buf[..len].first() {
+                // `[0][ack packet]`
+                Some(0) => {
+                    if let Some(ServerPacket::Ack { id }) = ServerPacket::decode(&mut &buf[1..len]) {
+                        if self.in_flight.as_ref().map(|in_flight| in_flight.id == id).unwrap_or(false) {
+                            self.in_flight = None;
+                        }
+                    }
+                },
+                // `[1][id u32][packet]`
+                Some(1) if len >= 5 => {
+                    let mut id = [0; 4];
+                    id.copy_from_slice(&buf[1..5]);
+                    let id = u32::from_le_bytes(id);
+
+                    self.queued.push_back(ClientPacket::Ack { id });
+
+                    if !self.seen.insert(id) {
+                        continue;
+                    }
+
+                    if let Some(packet) = ServerPacket::decode(&mut &buf[5..len]) {
+                        self.handle(now, rng, packet, private_keys, stats);
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        active
+    }
+
+    fn handle(&mut self, now: Instant, rng: &mut XorShiftRng, packet: ServerPacket, private_keys: &PrivateKeys, stats: &mut Stats) {
+        match packet {
+            ServerPacket::Ping => {
+                self.queued.push_back(ClientPacket::Pong);
+            },
+            ServerPacket::Create { session_key } => {
+                if let State::Creating { sent } = self.state {
+                    stats.create_latency.push(now - sent);
+
+                    let close_at = match self.role {
+                        Role::Close => Some(now + Duration::from_millis(rng.gen_range(200, 2000))),
+                        _ => None
+                    };
+
+                    if let Role::Private = self.role {
+                        private_keys.lock().unwrap().push_back(session_key.to_string());
+                    }
+
+                    self.state = State::Hosting { created: now, close_at };
+                }
+            },
+            ServerPacket::Join(Some(_)) => {
+                match self.state {
+                    State::Joining { sent } => {
+                        stats.matches += 1;
+                        stats.match_latency.push(now - sent);
+                    },
+                    State::Hosting { created, .. } => {
+                        stats.host_wait.push(now - created);
+                    },
+                    _ => {}
+                }
+
+                self.state = State::Idle { until: now + THINK_TIME };
+            },
+            ServerPacket::Join(None) => {
+                stats.failed_joins += 1;
+                self.state = State::Idle { until: now + BACKOFF };
+            },
+            ServerPacket::Error { code, .. } => {
+                stats.error(code);
+                self.state = State::Idle { until: now + BACKOFF };
+            },
+            ServerPacket::SessionExpired { .. } => {
+                stats.expired_sessions += 1;
+                self.state = State::Idle { until: now + THINK_TIME };
+            },
+            ServerPacket::Close { .. } => {
+                // the next packet reconnects as a new client
+                stats.dropped += 1;
+                self.next_id = 0;
+                self.seen.clear();
+                self.in_flight = None;
+                self.queued.clear();
+                self.state = State::Idle { until: now + BACKOFF };
+            },
+            _ => {}
+        }
+    }
+
+    fn flush(&mut self, now: Instant, rng: &mut XorShiftRng, stats: &mut Stats) -> bool {
+        if self.in_flight.is_none() {
+            if let Some(packet) = self.queued.pop_front() {
+                let mut data = self.next_id.to_le_bytes().to_vec();
+                packet.encode(&mut data);
+
+                self.in_flight = Some(InFlight { id: self.next_id, data, last_sent: None });
+                self.next_id += 1;
+            }
+        }
+
+        let in_flight = match &mut self.in_flight {
+            Some(in_flight) => in_flight,
+            None => return false
+        };
+
+        match in_flight.last_sent {
+            Some(last_sent) if now < last_sent + RESEND_DELAY => return false,
+            Some(_) => stats.resends += 1,
+            None => {}
+        }
+
+        in_flight.last_sent = Some(now);
+        stats.sent += 1;
+
+        if rng.gen_bool(self.loss) {
+            stats.lost += 1;
+        } else {
+            let _ = self.socket.send_to(&in_flight.data, self.server);
+        }
+
+        true
+    }
+}
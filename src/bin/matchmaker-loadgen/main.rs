@@ -0,0 +1,110 @@
+// Floods a server with bots over UDP and reports how it held up.
+// The server needs `--legacy-hashes` and the bots' hash in its hashes file.
+
+mod bot;
+mod options;
+mod report;
+
+use bot::{Bot, PrivateKeys, Role};
+use log::{error, info};
+use matchmaker::logging;
+use options::{Mix, Options, USAGE};
+use rand::prng::XorShiftRng;
+use rand::{Rng, SeedableRng};
+use report::Stats;
+use std::collections::VecDeque;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn main() {
+    logging::init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            error!(error:% = e; "Invalid arguments");
+            error!("{}", USAGE);
+            return;
+        }
+    };
+
+    info!(server:% = options.server, clients = options.clients, threads = options.threads; "Starting bots");
+
+    let private_keys: PrivateKeys = Arc::new(Mutex::new(VecDeque::new()));
+    let started = Instant::now();
+    let stop_at = started + options.ramp + options.duration;
+
+    let workers: Vec<_> = (0..options.threads)
+        .map(|worker| {
+            let options = options.clone();
+            let private_keys = private_keys.clone();
+
+            thread::spawn(move || run_worker(worker, &options, started, stop_at, &private_keys))
+        })
+        .collect();
+
+    let mut stats = Stats::default();
+
+    for worker in workers {
+        match worker.join() {
+            Ok(Ok(worker_stats)) => stats.merge(worker_stats),
+            Ok(Err(e)) => {
+                error!(error:% = e; "Aborting! Bots could not be started");
+                return;
+            },
+            Err(_) => error!("A worker thread panicked")
+        }
+    }
+
+    stats.print(options.clients, started.elapsed());
+}
+
+// Runs every `threads`th bot until `stop_at`
+fn run_worker(worker: usize, options: &Options, started: Instant, stop_at: Instant, private_keys: &PrivateKeys) -> std::io::Result<Stats> {
+    let mut rng = XorShiftRng::seed_from_u64(options.seed ^ worker as u64);
+    let mut stats = Stats::default();
+
+    let mut bots = Vec::new();
+
+    for index in (worker..options.clients).step_by(options.threads) {
+        let start_at = started + options.ramp.mul_f64(rng.gen::<f64>());
+        bots.push(Bot::new(options.server, role(&options.mix, index), &options.hash, options.loss, start_at)?);
+    }
+
+    loop {
+        let now = Instant::now();
+
+        if now >= stop_at {
+            return Ok(stats);
+        }
+
+        let mut active = false;
+
+        for bot in &mut bots {
+            active |= bot.update(now, &mut rng, private_keys, &mut stats);
+        }
+
+        if !active {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+// Spreads the roles over the bots in proportion to their weights
+fn role(mix: &Mix, index: usize) -> Role {
+    let slot = (index as u32) % mix.total();
+
+    if slot < mix.host {
+        Role::Host
+    } else if slot < mix.host + mix.join {
+        Role::Join
+    } else if slot < mix.host + mix.join + mix.private {
+        Role::Private
+    } else {
+        Role::Close
+    }
+}
@@ -0,0 +1,128 @@
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+
+pub const USAGE: &str = "Usage: matchmaker-loadgen <host:port> [--clients <n>] [--duration <seconds>] \
+    [--ramp <seconds>] [--mix host=<w>,join=<w>,private=<w>,close=<w>] [--loss <0.0-1.0>] \
+    [--threads <n>] [--hash <client hash>] [--seed <n>]";
+
+// How many bots of each kind, as relative weights
+#[derive(Clone, Debug)]
+pub struct Mix {
+    // hosts a public session and waits for a match
+    pub host: u32,
+    // joins a waiting private session by key, or any public one
+    pub join: u32,
+    // hosts a password protected session and shares its key with joiners
+    pub private: u32,
+    // hosts a public session and closes it before it's matched
+    pub close: u32
+}
+
+impl Mix {
+    fn parse(value: &str) -> Result<Mix, String> {
+        let mut mix = Mix { host: 0, join: 0, private: 0, close: 0 };
+
+        for part in value.split(',') {
+            let (name, weight) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected <role>=<weight>, got {}", part))?;
+
+            let weight = weight.parse().map_err(|_| format!("{} is not a whole number", weight))?;
+
+            match name {
+                "host" => mix.host = weight,
+                "join" => mix.join = weight,
+                "private" => mix.private = weight,
+                "close" => mix.close = weight,
+                _ => return Err(format!("unknown role {}", name))
+            }
+        }
+
+        if mix.total() == 0 {
+            return Err(String::from("at least one role needs a weight"));
+        }
+
+        Ok(mix)
+    }
+
+    pub fn total(&self) -> u32 {
+        self.host + self.join + self.private + self.close
+    }
+}
+
+impl Default for Mix {
+    fn default() -> Mix {
+        Mix { host: 40, join: 45, private: 5, close: 10 }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub server: SocketAddr,
+    pub clients: usize,
+    pub duration: Duration,
+    // bots start spread over this long
+    pub ramp: Duration,
+    pub mix: Mix,
+    // chance of dropping each datagram, in both directions
+    pub loss: f64,
+    pub threads: usize,
+    pub hash: String,
+    pub seed: u64
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut args = args.iter();
+
+        let server = args
+            .next()
+            .ok_or_else(|| String::from("missing server address"))?
+            .to_socket_addrs()
+            .map_err(|e| format!("server address: {}", e))?
+            .next()
+            .ok_or_else(|| String::from("server address did not resolve"))?;
+
+        let mut options = Options {
+            server,
+            clients: 1000,
+            duration: Duration::from_secs(30),
+            ramp: Duration::from_secs(1),
+            mix: Mix::default(),
+            loss: 0.0,
+            threads: 4,
+            hash: String::from("loadgen"),
+            seed: 0
+        };
+
+        while let Some(flag) = args.next() {
+            let value = args.next().ok_or_else(|| format!("{} needs a value", flag))?;
+
+            match flag.as_str() {
+                "--clients" => options.clients = parse(flag, value)?,
+                "--duration" => options.duration = Duration::from_secs_f64(parse(flag, value)?),
+                "--ramp" => options.ramp = Duration::from_secs_f64(parse(flag, value)?),
+                "--mix" => options.mix = Mix::parse(value).map_err(|e| format!("{}: {}", flag, e))?,
+                "--loss" => options.loss = parse(flag, value)?,
+                "--threads" => options.threads = parse(flag, value)?,
+                "--hash" => options.hash = value.clone(),
+                "--seed" => options.seed = parse(flag, value)?,
+                _ => return Err(format!("unknown option {}", flag))
+            }
+        }
+
+        if !(0.0..=1.0).contains(&options.loss) {
+            return Err(String::from("--loss must be between 0 and 1"));
+        }
+
+        if options.clients == 0 || options.threads == 0 {
+            return Err(String::from("--clients and --threads must be at least 1"));
+        }
+
+        Ok(options)
+    }
+}
+
+fn parse<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{}: invalid value {}", flag, value))
+}
@@ -0,0 +1,103 @@
+use matchmaker::packets::ErrorCode;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+// What one worker thread saw, merged into a single report at the end
+#[derive(Default)]
+pub struct Stats {
+    pub creates: u64,
+    pub joins: u64,
+    pub closes: u64,
+    pub matches: u64,
+    pub failed_joins: u64,
+    pub errors: BTreeMap<u16, u64>,
+    pub expired_sessions: u64,
+    // closes from the server, the bot was dropped
+    pub dropped: u64,
+    pub sent: u64,
+    pub received: u64,
+    pub resends: u64,
+    pub lost: u64,
+    // create sent to session key received
+    pub create_latency: Vec<Duration>,
+    // join sent to match received, on the joiner
+    pub match_latency: Vec<Duration>,
+    // session key received to match received, on the host
+    pub host_wait: Vec<Duration>
+}
+
+impl Stats {
+    pub fn merge(&mut self, other: Stats) {
+        self.creates += other.creates;
+        self.joins += other.joins;
+        self.closes += other.closes;
+        self.matches += other.matches;
+        self.failed_joins += other.failed_joins;
+        self.expired_sessions += other.expired_sessions;
+        self.dropped += other.dropped;
+        self.sent += other.sent;
+        self.received += other.received;
+        self.resends += other.resends;
+        self.lost += other.lost;
+        self.create_latency.extend(other.create_latency);
+        self.match_latency.extend(other.match_latency);
+        self.host_wait.extend(other.host_wait);
+
+        for (code, count) in other.errors {
+            *self.errors.entry(code).or_default() += count;
+        }
+    }
+
+    pub fn error(&mut self, code: ErrorCode) {
+        *self.errors.entry(code as u16).or_default() += 1;
+    }
+
+    pub fn print(&mut self, clients: usize, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let requests = self.creates + self.joins;
+        let errors: u64 = self.errors.values().sum();
+
+        println!("clients          {} over {:.1}s", clients, seconds);
+        println!("matches          {} ({:.1}/s)", self.matches, self.matches as f64 / seconds);
+        println!("requests         {} creates, {} joins, {} closes ({:.1}/s)", self.creates, self.joins, self.closes, requests as f64 / seconds);
+        println!("match latency    {}", percentiles(&mut self.match_latency));
+        println!("create latency   {}", percentiles(&mut self.create_latency));
+        println!("host wait        {}", percentiles(&mut self.host_wait));
+        println!("failed joins     {} ({})", self.failed_joins, rate(self.failed_joins, self.joins));
+        println!("errors           {} ({})", errors, rate(errors, requests));
+
+        for (code, count) in &self.errors {
+            println!("  code {:<10} {}", code, count);
+        }
+
+        println!("expired sessions {}", self.expired_sessions);
+        println!("dropped clients  {}", self.dropped);
+        println!("datagrams        {} sent, {} received, {} resent, {} lost on purpose", self.sent, self.received, self.resends, self.lost);
+    }
+}
+
+fn rate(count: u64, total: u64) -> String {
+    if total == 0 {
+        return String::from("-");
+    }
+
+    format!("{:.2}%", count as f64 * 100.0 / total as f64)
+}
+
+fn percentiles(samples: &mut [Duration]) -> String {
+    if samples.is_empty() {
+        return String::from("no samples");
+    }
+
+    samples.sort();
+
+    let at = |p: f64| samples[((samples.len() - 1) as f64 * p).round() as usize];
+
+    format!("p50 {} p90 {} p99 {} max {} ({} samples)",
+        millis(at(0.5)), millis(at(0.9)), millis(at(0.99)), millis(samples[samples.len() - 1]), samples.len()
+    )
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.1}ms", duration.as_secs_f64() * 1000.0)
+}
@@ -0,0 +1,2 @@
+mod server_config;
+pub use server_config::{ServerConfig, RESTART_KEYS};
\ No newline at end of file
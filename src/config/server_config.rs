@@ -0,0 +1,535 @@
+use crate::logging::{LogFilter, LogFormat};
+use serde::Deserialize;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const DEFAULT_CONFIG_PATH: &str = "./matchmaker.toml";
+
+const ENV_PREFIX: &str = "MATCHMAKER_";
+
+// Every setting can come from the TOML file, a `MATCHMAKER_<NAME>`
+// environment variable or a `--<name>` flag, in increasing precedence
+const KEYS: [&str; 27] = [
+    "port",
+    "bind_address",
+    "max_silence_duration",
+    "max_ping_pong_rate",
+    "tick_rate",
+    "key_length",
+    "receive_buffer_size",
+    "worker_threads",
+    "hashes_path",
+    "legacy_hashes",
+    "secrets_path",
+    "access_list_path",
+    "server_key_path",
+    "shutdown_drain_time",
+    "shutdown_ack_timeout",
+    "log_level",
+    "log_format",
+    "metrics_address",
+    "admin_address",
+    "admin_token",
+    "maintenance",
+    "maintenance_message",
+    "motd",
+    "sessions_path",
+    "snapshot_interval",
+    "max_session_age",
+    "max_waiting_time"
+];
+
+// Settings the running server cannot pick up on reload
+pub const RESTART_KEYS: [&str; 10] = [
+    "port",
+    "bind_address",
+    "tick_rate",
+    "receive_buffer_size",
+    "worker_threads",
+    "server_key_path",
+    "access_list_path",
+    "metrics_address",
+    "admin_address",
+    "admin_token"
+];
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub port: u16,
+    pub bind_address: String,
+    // seconds without a packet before a client is dropped
+    pub max_silence_duration: f32,
+    // seconds between pings
+    pub max_ping_pong_rate: f32,
+    // ticks per second, also sets the delay before resending unacknowledged packets
+    pub tick_rate: f64,
+    pub key_length: usize,
+    pub receive_buffer_size: usize,
+    // tasks that ack, order and resend client packets, 0 to do it all on the server task
+    pub worker_threads: usize,
+    pub hashes_path: String,
+    pub legacy_hashes: bool,
+    pub secrets_path: String,
+    pub access_list_path: String,
+    pub server_key_path: String,
+    // seconds to wait on shutdown for open sessions to be matched while refusing new ones
+    pub shutdown_drain_time: f32,
+    // seconds to wait on shutdown for clients to acknowledge the close packet
+    pub shutdown_ack_timeout: f32,
+    // a default level and optional per target levels, like `info,packets=debug`
+    pub log_level: String,
+    // `text` or `json`
+    pub log_format: String,
+    // `ip:port` to serve Prometheus metrics at `/metrics`, empty to disable
+    pub metrics_address: String,
+    // `ip:port` to accept admin commands at `/command`, empty to disable
+    pub admin_address: String,
+    // bearer token the admin endpoint requires, empty to allow anyone who can connect
+    pub admin_token: String,
+    // refuse new sessions and joins, can also be toggled from the admin endpoint
+    pub maintenance: bool,
+    // sent with the error to clients refused during maintenance
+    pub maintenance_message: String,
+    // notice sent to every client when it connects, empty to send none
+    pub motd: String,
+    // open sessions are saved here and restored on the next start
+    pub sessions_path: String,
+    // seconds between session snapshots, 0 to disable saving and restoring
+    pub snapshot_interval: f32,
+    // seconds a session may stay open, 0 for no limit
+    pub max_session_age: f32,
+    // seconds a session may stay open without its host sending anything but pongs and acks, 0 for no limit
+    pub max_waiting_time: f32
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            port: 0,
+            bind_address: String::from("0.0.0.0"),
+            max_silence_duration: 30.0,
+            max_ping_pong_rate: 5.0,
+            tick_rate: 20.0,
+            key_length: 7,
+            receive_buffer_size: 1024,
+            worker_threads: 0,
+            hashes_path: String::from("./hashes.txt"),
+            legacy_hashes: true,
+            secrets_path: String::from("./secrets.txt"),
+            access_list_path: String::from("./access.txt"),
+            server_key_path: String::from("./server_key"),
+            shutdown_drain_time: 0.0,
+            shutdown_ack_timeout: 2.0,
+            log_level: String::from("info"),
+            log_format: String::from("text"),
+            metrics_address: String::new(),
+            admin_address: String::new(),
+            admin_token: String::new(),
+            maintenance: false,
+            maintenance_message: String::from("Server is down for maintenance"),
+            motd: String::new(),
+            sessions_path: String::from("./sessions.json"),
+            snapshot_interval: 10.0,
+            max_session_age: 0.0,
+            max_waiting_time: 0.0
+        }
+    }
+}
+
+impl ServerConfig {
+    // Builds the config from the config file, environment and command line.
+    // `args` excludes the program name. A bare first argument is the port.
+    pub fn load(args: &[String]) -> Result<ServerConfig, Vec<String>> {
+        let overrides = parse_args(args).map_err(|e| vec![e])?;
+
+        let mut config = match ServerConfig::file_path(args) {
+            Some(path) => ServerConfig::from_file(&path).map_err(|e| vec![e])?,
+            None => ServerConfig::default()
+        };
+
+        let mut errors = Vec::new();
+
+        for key in KEYS.iter() {
+            let name = format!("{}{}", ENV_PREFIX, key.to_uppercase());
+
+            if let Ok(value) = std::env::var(&name) {
+                if let Err(e) = config.set(key, &value) {
+                    errors.push(format!("{}: {}", name, e));
+                }
+            }
+        }
+
+        for (key, value) in overrides.iter().filter(|(key, _)| key != "config") {
+            if let Err(e) = config.set(key, value) {
+                errors.push(format!("--{}: {}", key.replace('_', "-"), e));
+            }
+        }
+
+        errors.extend(config.validate());
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+
+    // The config file `load` reads for these arguments, if any
+    pub fn file_path(args: &[String]) -> Option<String> {
+        let flag = parse_args(args)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|(key, _)| key == "config")
+            .map(|(_, value)| value);
+
+        match flag.or_else(|| std::env::var(format!("{}CONFIG", ENV_PREFIX)).ok()) {
+            Some(path) => Some(path),
+            None if std::path::Path::new(DEFAULT_CONFIG_PATH).exists() => Some(String::from(DEFAULT_CONFIG_PATH)),
+            None => None
+        }
+    }
+
+    pub fn from_file(path: &str) -> Result<ServerConfig, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "port" => self.port = parse(value)?,
+            "bind_address" => self.bind_address = value.to_string(),
+            "max_silence_duration" => self.max_silence_duration = parse(value)?,
+            "max_ping_pong_rate" => self.max_ping_pong_rate = parse(value)?,
+            "tick_rate" => self.tick_rate = parse(value)?,
+            "key_length" => self.key_length = parse(value)?,
+            "receive_buffer_size" => self.receive_buffer_size = parse(value)?,
+            "worker_threads" => self.worker_threads = parse(value)?,
+            "hashes_path" => self.hashes_path = value.to_string(),
+            "legacy_hashes" => self.legacy_hashes = parse(value)?,
+            "secrets_path" => self.secrets_path = value.to_string(),
+            "access_list_path" => self.access_list_path = value.to_string(),
+            "server_key_path" => self.server_key_path = value.to_string(),
+            "shutdown_drain_time" => self.shutdown_drain_time = parse(value)?,
+            "shutdown_ack_timeout" => self.shutdown_ack_timeout = parse(value)?,
+            "log_level" => self.log_level = value.to_string(),
+            "log_format" => self.log_format = value.to_string(),
+            "metrics_address" => self.metrics_address = value.to_string(),
+            "admin_address" => self.admin_address = value.to_string(),
+            "admin_token" => self.admin_token = value.to_string(),
+            "maintenance" => self.maintenance = parse(value)?,
+            "maintenance_message" => self.maintenance_message = value.to_string(),
+            "motd" => self.motd = value.to_string(),
+            "sessions_path" => self.sessions_path = value.to_string(),
+            "snapshot_interval" => self.snapshot_interval = parse(value)?,
+            "max_session_age" => self.max_session_age = parse(value)?,
+            "max_waiting_time" => self.max_waiting_time = parse(value)?,
+            _ => return Err(String::from("unknown setting"))
+        }
+
+        Ok(())
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        let value = match key {
+            "port" => self.port.to_string(),
+            "bind_address" => self.bind_address.clone(),
+            "max_silence_duration" => self.max_silence_duration.to_string(),
+            "max_ping_pong_rate" => self.max_ping_pong_rate.to_string(),
+            "tick_rate" => self.tick_rate.to_string(),
+            "key_length" => self.key_length.to_string(),
+            "receive_buffer_size" => self.receive_buffer_size.to_string(),
+            "worker_threads" => self.worker_threads.to_string(),
+            "hashes_path" => self.hashes_path.clone(),
+            "legacy_hashes" => self.legacy_hashes.to_string(),
+            "secrets_path" => self.secrets_path.clone(),
+            "access_list_path" => self.access_list_path.clone(),
+            "server_key_path" => self.server_key_path.clone(),
+            "shutdown_drain_time" => self.shutdown_drain_time.to_string(),
+            "shutdown_ack_timeout" => self.shutdown_ack_timeout.to_string(),
+            "log_level" => self.log_level.clone(),
+            "log_format" => self.log_format.clone(),
+            "metrics_address" => self.metrics_address.clone(),
+            "admin_address" => self.admin_address.clone(),
+            "admin_token" => self.admin_token.clone(),
+            "maintenance" => self.maintenance.to_string(),
+            "maintenance_message" => self.maintenance_message.clone(),
+            "motd" => self.motd.clone(),
+            "sessions_path" => self.sessions_path.clone(),
+            "snapshot_interval" => self.snapshot_interval.to_string(),
+            "max_session_age" => self.max_session_age.to_string(),
+            "max_waiting_time" => self.max_waiting_time.to_string(),
+            _ => return None
+        };
+
+        Some(value)
+    }
+
+    // (setting, old value, new value) for every setting that differs
+    pub fn changes(&self, other: &ServerConfig) -> Vec<(&'static str, String, String)> {
+        KEYS.iter()
+            .filter_map(|key| {
+                let old = self.get(key)?;
+                let new = other.get(key)?;
+
+                if old == new { None } else { Some((*key, old, new)) }
+            })
+            .collect()
+    }
+
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.port == 0 {
+            errors.push(String::from("port must be set to a number between 1 and 65535"));
+        }
+
+        if self.bind_address.parse::<IpAddr>().is_err() {
+            errors.push(format!("bind_address `{}` is not an ip address", self.bind_address));
+        }
+
+        if !is_positive(self.max_silence_duration.into()) {
+            errors.push(String::from("max_silence_duration must be greater than 0"));
+        }
+
+        if !is_positive(self.max_ping_pong_rate.into()) || self.max_ping_pong_rate >= self.max_silence_duration {
+            errors.push(String::from("max_ping_pong_rate must be greater than 0 and less than max_silence_duration"));
+        }
+
+        if !is_positive(self.tick_rate) || self.tick_rate > 1000.0 {
+            errors.push(String::from("tick_rate must be greater than 0 and at most 1000"));
+        }
+
+        // keys are sent as u8 length prefixed strings
+        if self.key_length < 4 || self.key_length > u8::MAX as usize {
+            errors.push(String::from("key_length must be between 4 and 255"));
+        }
+
+        if self.receive_buffer_size < 64 || self.receive_buffer_size > 65535 {
+            errors.push(String::from("receive_buffer_size must be between 64 and 65535"));
+        }
+
+        if self.worker_threads > 256 {
+            errors.push(String::from("worker_threads must be at most 256"));
+        }
+
+        if !is_non_negative(self.shutdown_drain_time.into()) || !is_non_negative(self.shutdown_ack_timeout.into()) {
+            errors.push(String::from("shutdown_drain_time and shutdown_ack_timeout must not be negative"));
+        }
+
+        if !is_non_negative(self.snapshot_interval.into()) {
+            errors.push(String::from("snapshot_interval must not be negative"));
+        }
+
+        if !is_non_negative(self.max_session_age.into()) || !is_non_negative(self.max_waiting_time.into()) {
+            errors.push(String::from("max_session_age and max_waiting_time must not be negative"));
+        }
+
+        if let Err(e) = self.log_level.parse::<LogFilter>() {
+            errors.push(format!("log_level: {}", e));
+        }
+
+        if let Err(e) = self.log_format.parse::<LogFormat>() {
+            errors.push(format!("log_format: {}", e));
+        }
+
+        if !self.metrics_address.is_empty() && self.metrics_address.parse::<SocketAddr>().is_err() {
+            errors.push(format!("metrics_address `{}` is not an ip:port address", self.metrics_address));
+        }
+
+        if !self.admin_address.is_empty() && self.admin_address.parse::<SocketAddr>().is_err() {
+            errors.push(format!("admin_address `{}` is not an ip:port address", self.admin_address));
+        }
+
+        errors
+    }
+
+    pub fn tick_duration(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.tick_rate)
+    }
+
+    pub fn metrics_address(&self) -> Option<SocketAddr> {
+        self.metrics_address.parse().ok()
+    }
+
+    pub fn admin_address(&self) -> Option<SocketAddr> {
+        self.admin_address.parse().ok()
+    }
+}
+
+// also rejects NaN
+fn is_positive(value: f64) -> bool {
+    value > 0.0
+}
+
+fn is_non_negative(value: f64) -> bool {
+    value >= 0.0
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, String> {
+    value.trim().parse::<T>().map_err(|_| format!("`{}` is not a valid value", value))
+}
+
+// Turns `3000 --tick-rate 30 --legacy-hashes --config=dev.toml` into
+// [("port", "3000"), ("tick_rate", "30"), ("legacy_hashes", "true"), ("config", "dev.toml")]
+fn parse_args(args: &[String]) -> Result<Vec<(String, String)>, String> {
+    let mut result = Vec::new();
+    let mut iter = args.iter().peekable();
+
+    if let Some(first) = iter.peek() {
+        if !first.starts_with("--") {
+            result.push((String::from("port"), first.to_string()));
+            iter.next();
+        }
+    }
+
+    while let Some(arg) = iter.next() {
+        let flag = match arg.strip_prefix("--") {
+            Some(flag) => flag,
+            None => return Err(format!("unexpected argument `{}`", arg))
+        };
+
+        let (key, value) = match flag.find('=') {
+            Some(position) => (&flag[..position], Some(flag[position + 1..].to_string())),
+            None => (flag, None)
+        };
+
+        let key = key.replace('-', "_");
+
+        let value = match value {
+            Some(value) => value,
+            // boolean flags may be given without a value
+            None if key == "legacy_hashes" || key == "maintenance" => String::from("true"),
+            None => match iter.next() {
+                Some(value) => value.to_string(),
+                None => return Err(format!("--{} needs a value", flag))
+            }
+        };
+
+        result.push((key, value));
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    fn config_file(name: &str, text: &str) -> String {
+        let path = std::env::temp_dir().join(format!("matchmaker-config-{}-{}.toml", name, std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    // The only test that sets environment variables, the others pass
+    // every setting they check as a flag so they can't be affected
+    #[test]
+    fn later_sources_override_earlier_ones() {
+        let path = config_file("precedence", "tick_rate = 10.0\nkey_length = 9\nmotd = \"from the file\"\n");
+
+        std::env::set_var("MATCHMAKER_TICK_RATE", "30");
+        std::env::set_var("MATCHMAKER_KEY_LENGTH", "8");
+
+        let config = ServerConfig::load(&args(&["3000", "--config", &path, "--tick-rate", "60"]));
+
+        std::env::remove_var("MATCHMAKER_TICK_RATE");
+        std::env::remove_var("MATCHMAKER_KEY_LENGTH");
+        std::fs::remove_file(&path).unwrap();
+
+        let config = config.unwrap();
+
+        assert_eq!(config.port, 3000);
+        assert_eq!(config.motd, "from the file");
+        assert_eq!(config.key_length, 8);
+        assert_eq!(config.tick_rate, 60.0);
+        assert_eq!(config.max_silence_duration, ServerConfig::default().max_silence_duration);
+    }
+
+    #[test]
+    fn flag_values_follow_an_equals_sign_or_a_space() {
+        let parsed = parse_args(&args(&[
+            "3000",
+            "--tick-rate", "30",
+            "--bind-address=127.0.0.1",
+            "--motd=a=b",
+            "--legacy-hashes",
+            "--maintenance=false",
+            "--config", "dev.toml"
+        ]));
+
+        assert_eq!(parsed, Ok(pairs(&[
+            ("port", "3000"),
+            ("tick_rate", "30"),
+            ("bind_address", "127.0.0.1"),
+            ("motd", "a=b"),
+            ("legacy_hashes", "true"),
+            ("maintenance", "false"),
+            ("config", "dev.toml")
+        ])));
+    }
+
+    #[test]
+    fn bad_arguments_are_errors() {
+        assert!(parse_args(&args(&["3000", "--tick-rate"])).is_err());
+        assert!(parse_args(&args(&["3000", "4000"])).is_err());
+        assert_eq!(parse_args(&args(&[])), Ok(vec![]));
+    }
+
+    #[test]
+    fn validate_reports_every_invalid_setting() {
+        let valid = ServerConfig { port: 3000, ..ServerConfig::default() };
+        assert_eq!(valid.validate(), Vec::<String>::new());
+
+        let invalid = ServerConfig {
+            port: 0,
+            tick_rate: 0.0,
+            key_length: 2,
+            log_format: String::from("xml"),
+            admin_address: String::from("localhost"),
+            ..ServerConfig::default()
+        };
+
+        let errors = invalid.validate();
+
+        assert_eq!(errors.len(), 5, "{:?}", errors);
+        assert!(errors.iter().any(|e| e.starts_with("port")));
+        assert!(errors.iter().any(|e| e.starts_with("tick_rate")));
+        assert!(errors.iter().any(|e| e.starts_with("key_length")));
+        assert!(errors.iter().any(|e| e.starts_with("log_format")));
+        assert!(errors.iter().any(|e| e.starts_with("admin_address")));
+    }
+
+    #[test]
+    fn load_collects_flag_and_validation_errors() {
+        let path = config_file("errors", "");
+        let errors = ServerConfig::load(&args(&["--config", &path, "--port", "3000", "--tick-rate", "fast", "--key-length=2", "--no-such", "1"]));
+        std::fs::remove_file(&path).unwrap();
+
+        let errors = errors.unwrap_err();
+
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert!(errors.iter().any(|e| e.starts_with("--tick-rate:")));
+        assert!(errors.iter().any(|e| e.starts_with("--no-such:")));
+        assert!(errors.iter().any(|e| e.starts_with("key_length")));
+    }
+
+    #[test]
+    fn unknown_settings_in_the_file_are_errors() {
+        let path = config_file("unknown", "tick_rat = 10.0\n");
+        let result = ServerConfig::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(result.is_err());
+    }
+}
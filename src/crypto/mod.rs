@@ -0,0 +1,2 @@
+mod transport_cipher;
+pub use transport_cipher::{TransportCipher, TransportKeys};
\ No newline at end of file
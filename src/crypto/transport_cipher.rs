@@ -0,0 +1,148 @@
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use rand::Rng;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+pub const PUBLIC_KEY_LEN: usize = 32;
+
+// Seals datagrams in one direction. Nonces are never sent: both ends
+// derive them from the packet type and packet id carried in the clear
+// header, which is also authenticated as associated data.
+#[derive(Clone)]
+pub struct TransportCipher {
+    aead: ChaCha20Poly1305
+}
+
+impl TransportCipher {
+    fn new(key: &[u8; 32]) -> TransportCipher {
+        TransportCipher { aead: ChaCha20Poly1305::new(Key::from_slice(key)) }
+    }
+
+    fn nonce(kind: u8, id: u32) -> Nonce {
+        let mut nonce = [0u8; 12];
+        nonce[0] = kind;
+        nonce[4..8].copy_from_slice(&id.to_le_bytes());
+        *Nonce::from_slice(&nonce)
+    }
+
+    pub fn seal(&self, kind: u8, id: u32, header: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let payload = Payload { msg: plaintext, aad: header };
+
+        // encryption only fails for messages far larger than a datagram
+        self.aead
+            .encrypt(&TransportCipher::nonce(kind, id), payload)
+            .unwrap_or_default()
+    }
+
+    pub fn open(&self, kind: u8, id: u32, header: &[u8], ciphertext: &[u8]) -> Option<Vec<u8>> {
+        let payload = Payload { msg: ciphertext, aad: header };
+
+        self.aead.decrypt(&TransportCipher::nonce(kind, id), payload).ok()
+    }
+}
+
+// One cipher per direction, derived from an X25519 exchange
+pub struct TransportKeys {
+    pub public_key: [u8; PUBLIC_KEY_LEN],
+    pub to_client: TransportCipher,
+    pub from_client: TransportCipher
+}
+
+impl TransportKeys {
+    // Answers the client's ephemeral public key with our own and derives
+    // the directional keys with HKDF-SHA256 over the shared secret
+    pub fn exchange(client_public_key: &[u8]) -> Option<TransportKeys> {
+        if client_public_key.len() != PUBLIC_KEY_LEN {
+            return None;
+        }
+
+        let mut client_bytes = [0u8; PUBLIC_KEY_LEN];
+        client_bytes.copy_from_slice(client_public_key);
+        let client_public_key = PublicKey::from(client_bytes);
+
+        let mut seed = [0u8; 32];
+        rand::thread_rng().fill(&mut seed);
+
+        let secret = StaticSecret::from(seed);
+        let public_key = PublicKey::from(&secret);
+        let shared = secret.diffie_hellman(&client_public_key);
+
+        if !shared.was_contributory() {
+            return None;
+        }
+
+        let mut salt = Vec::with_capacity(PUBLIC_KEY_LEN * 2);
+        salt.extend(client_public_key.as_bytes());
+        salt.extend(public_key.as_bytes());
+
+        let hkdf = Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes());
+
+        let mut to_client = [0u8; 32];
+        let mut from_client = [0u8; 32];
+
+        hkdf.expand(b"matchmaker server to client", &mut to_client).ok()?;
+        hkdf.expand(b"matchmaker client to server", &mut from_client).ok()?;
+
+        Some(TransportKeys {
+            public_key: *public_key.as_bytes(),
+            to_client: TransportCipher::new(&to_client),
+            from_client: TransportCipher::new(&from_client)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &[u8] = &[1, 7, 0, 0, 0];
+
+    fn sealed() -> Vec<u8> {
+        TransportCipher::new(&[1; 32]).seal(1, 7, HEADER, b"hello")
+    }
+
+    #[test]
+    fn sealed_packets_open_with_the_same_key_type_id_and_header() {
+        let cipher = TransportCipher::new(&[1; 32]);
+
+        assert_eq!(cipher.open(1, 7, HEADER, &sealed()), Some(b"hello".to_vec()));
+    }
+
+    #[test]
+    fn the_wrong_key_fails() {
+        assert_eq!(TransportCipher::new(&[2; 32]).open(1, 7, HEADER, &sealed()), None);
+    }
+
+    #[test]
+    fn the_wrong_packet_type_or_id_fails() {
+        let cipher = TransportCipher::new(&[1; 32]);
+
+        assert_eq!(cipher.open(0, 7, HEADER, &sealed()), None);
+        assert_eq!(cipher.open(1, 8, HEADER, &sealed()), None);
+    }
+
+    #[test]
+    fn a_changed_header_fails() {
+        let cipher = TransportCipher::new(&[1; 32]);
+
+        for index in 0..HEADER.len() {
+            let mut header = HEADER.to_vec();
+            header[index] ^= 0x01;
+
+            assert_eq!(cipher.open(1, 7, &header, &sealed()), None, "header byte {} was changed", index);
+        }
+
+        assert_eq!(cipher.open(1, 7, &HEADER[1..], &sealed()), None);
+    }
+
+    #[test]
+    fn a_changed_ciphertext_fails() {
+        let cipher = TransportCipher::new(&[1; 32]);
+        let mut changed = sealed();
+        changed[0] ^= 0x01;
+
+        assert_eq!(cipher.open(1, 7, HEADER, &changed), None);
+    }
+}
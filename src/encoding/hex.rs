@@ -0,0 +1,40 @@
+// Lowercase, two characters per byte
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Either case is accepted, None if the text is not whole bytes of hex
+pub fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_survive_a_round_trip() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let text = encode_hex(&bytes);
+
+        assert_eq!(&text[..6], "000102");
+        assert_eq!(decode_hex(&text), Some(bytes));
+        assert_eq!(decode_hex("00FFaB"), Some(vec![0x00, 0xff, 0xab]));
+        assert_eq!(decode_hex(""), Some(Vec::new()));
+    }
+
+    #[test]
+    fn anything_but_whole_bytes_of_hex_is_refused() {
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz11"), None);
+        assert_eq!(decode_hex("+1"), None);
+        assert_eq!(decode_hex("é1"), None);
+    }
+}
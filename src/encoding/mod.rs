@@ -0,0 +1,2 @@
+mod hex;
+pub(crate) use hex::{decode_hex, encode_hex};
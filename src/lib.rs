@@ -0,0 +1,14 @@
+mod access;
+pub mod attestation;
+pub mod config;
+mod crypto;
+mod encoding;
+pub mod logging;
+mod metrics;
+pub mod packets;
+mod persistence;
+pub mod runtime;
+pub mod server;
+pub mod simulation;
+pub mod threads;
+pub mod tickets;
@@ -0,0 +1,225 @@
+use log::kv::{Error, Key, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const CRATE_NAME: &str = "matchmaker";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LogFormat {
+    Text,
+    Json
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LogFormat, String> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("log format `{}` must be `text` or `json`", s))
+        }
+    }
+}
+
+// A default level with optional per target levels, written like
+// `info,packets=debug,threads=warn`
+#[derive(Clone, PartialEq, Debug)]
+pub struct LogFilter {
+    default: LevelFilter,
+    targets: Vec<(String, LevelFilter)>
+}
+
+impl LogFilter {
+    const fn new() -> LogFilter {
+        LogFilter { default: LevelFilter::Info, targets: Vec::new() }
+    }
+
+    fn level(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .find(|(name, _)| name == target)
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, std::cmp::max)
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LogFilter, String> {
+        let mut filter = LogFilter::new();
+
+        for part in s.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            let parse_level = |level: &str| {
+                level.parse::<LevelFilter>().map_err(|_| format!("`{}` is not a log level", level))
+            };
+
+            match part.find('=') {
+                Some(position) => {
+                    let level = parse_level(&part[position + 1..])?;
+                    filter.targets.push((part[..position].to_string(), level));
+                },
+                None => filter.default = parse_level(part)?
+            }
+        }
+
+        Ok(filter)
+    }
+}
+
+struct Logger {
+    settings: RwLock<(LogFilter, LogFormat)>
+}
+
+static LOGGER: Logger = Logger {
+    settings: RwLock::new((LogFilter::new(), LogFormat::Text))
+};
+
+// Installs the logger at the default `info` level as text
+pub fn init() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(LevelFilter::Info);
+    }
+}
+
+pub fn configure(filter: &str, format: &str) -> Result<(), String> {
+    let filter = filter.parse::<LogFilter>()?;
+    let format = format.parse::<LogFormat>()?;
+
+    log::set_max_level(filter.max_level());
+
+    if let Ok(mut settings) = LOGGER.settings.write() {
+        *settings = (filter, format);
+    }
+
+    Ok(())
+}
+
+// Module paths become short targets: `matchmaker::packets::packets` logs as
+// `packets`, and the crate root (the server itself) as `server`
+fn short_target(target: &str) -> &str {
+    match target.strip_prefix(CRATE_NAME) {
+        Some("") => "server",
+        // `matchmaker::threads::...` or another binary like `matchmaker_loadgen`
+        Some(rest) => rest.trim_start_matches([':', '_']).split("::").next().unwrap_or(rest),
+        None => target
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match self.settings.read() {
+            Ok(settings) => metadata.level() <= settings.0.level(short_target(metadata.target())),
+            Err(_) => metadata.level() <= Level::Info
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let format = self.settings.read().map(|settings| settings.1).unwrap_or(LogFormat::Text);
+        let target = short_target(record.target());
+
+        let mut fields = Fields(Vec::new());
+        let _ = record.key_values().visit(&mut fields);
+
+        let line = match format {
+            LogFormat::Text => {
+                let mut line = format!("{} {:<5} {}: {}", timestamp(), record.level(), target, record.args());
+
+                for (key, value) in fields.0 {
+                    match value {
+                        serde_json::Value::String(value) => line += &format!(" {}={}", key, value),
+                        value => line += &format!(" {}={}", key, value)
+                    }
+                }
+
+                line
+            },
+            LogFormat::Json => {
+                let mut object = serde_json::Map::new();
+
+                object.insert(String::from("ts"), timestamp().into());
+                object.insert(String::from("level"), record.level().as_str().into());
+                object.insert(String::from("target"), target.into());
+                object.insert(String::from("msg"), record.args().to_string().into());
+
+                for (key, value) in fields.0 {
+                    object.insert(key, value);
+                }
+
+                serde_json::Value::Object(object).to_string()
+            }
+        };
+
+        let _ = writeln!(std::io::stdout().lock(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stdout().flush();
+    }
+}
+
+// Numbers and booleans keep their type in JSON output
+struct Fields(Vec<(String, serde_json::Value)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+        let value = if let Some(value) = value.to_u64() {
+            value.into()
+        } else if let Some(value) = value.to_i64() {
+            value.into()
+        } else if let Some(value) = value.to_bool() {
+            value.into()
+        } else if let Some(value) = value.to_f64().and_then(serde_json::Number::from_f64) {
+            serde_json::Value::Number(value)
+        } else {
+            value.to_string().into()
+        };
+
+        self.0.push((key.to_string(), value));
+        Ok(())
+    }
+}
+
+// RFC 3339 in UTC with milliseconds
+fn timestamp() -> String {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let days = (seconds / 86400) as i64;
+    let time = seconds % 86400;
+
+    // days to civil date, from Howard Hinnant's date algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        since_epoch.subsec_millis()
+    )
+}
@@ -0,0 +1,2 @@
+mod logger;
+pub use logger::{init, configure, LogFilter, LogFormat};
\ No newline at end of file
@@ -0,0 +1,154 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Every packet name used by `ClientPacket::name` and `ServerPacket::name`
+const PACKET_TYPES: [&str; 13] = [
+    "ping_pong",
+    "ack",
+    "create",
+    "join",
+    "close",
+    "error",
+    "challenge",
+    "attest",
+    "key_exchange",
+    "notice",
+    "resume",
+    "session_expired",
+    "sealed"
+];
+
+#[derive(Clone, Copy)]
+pub enum JoinFailure {
+    NotAttested = 0,
+    NoOpenSession = 1,
+    SessionNotFound = 2,
+    Maintenance = 3
+}
+
+const JOIN_FAILURE_REASONS: [&str; 4] = [
+    "not_attested",
+    "no_open_session",
+    "session_not_found",
+    "maintenance"
+];
+
+// Shared by every thread, rendered in the Prometheus text format
+pub struct Metrics {
+    connected_clients: AtomicU64,
+    public_sessions: AtomicU64,
+    private_sessions: AtomicU64,
+    matches: AtomicU64,
+    expired_sessions: AtomicU64,
+    join_failures: [AtomicU64; JOIN_FAILURE_REASONS.len()],
+    packets_received: [AtomicU64; PACKET_TYPES.len()],
+    packets_sent: [AtomicU64; PACKET_TYPES.len()],
+    retransmits: AtomicU64,
+    unknown_packets: AtomicU64,
+    tick_overruns: AtomicU64
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: AtomicU64 = AtomicU64::new(0);
+
+pub static METRICS: Metrics = Metrics {
+    connected_clients: ZERO,
+    public_sessions: ZERO,
+    private_sessions: ZERO,
+    matches: ZERO,
+    expired_sessions: ZERO,
+    join_failures: [ZERO; JOIN_FAILURE_REASONS.len()],
+    packets_received: [ZERO; PACKET_TYPES.len()],
+    packets_sent: [ZERO; PACKET_TYPES.len()],
+    retransmits: ZERO,
+    unknown_packets: ZERO,
+    tick_overruns: ZERO
+};
+
+fn packet_index(name: &str) -> Option<usize> {
+    PACKET_TYPES.iter().position(|packet_type| *packet_type == name)
+}
+
+impl Metrics {
+    pub fn set_clients(&self, clients: usize) {
+        self.connected_clients.store(clients as u64, Ordering::Relaxed);
+    }
+
+    pub fn set_sessions(&self, public: usize, private: usize) {
+        self.public_sessions.store(public as u64, Ordering::Relaxed);
+        self.private_sessions.store(private as u64, Ordering::Relaxed);
+    }
+
+    pub fn match_made(&self) {
+        self.matches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn session_expired(&self) {
+        self.expired_sessions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn join_failed(&self, reason: JoinFailure) {
+        self.join_failures[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn packet_received(&self, name: &str) {
+        if let Some(index) = packet_index(name) {
+            self.packets_received[index].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn packet_sent(&self, name: &str) {
+        if let Some(index) = packet_index(name) {
+            self.packets_sent[index].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn retransmitted(&self) {
+        self.retransmits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn unknown_packet(&self) {
+        self.unknown_packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn tick_overrun(&self) {
+        self.tick_overruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let single = |out: &mut String, name: &str, kind: &str, help: &str, value: &AtomicU64| {
+            let _ = writeln!(out, "# HELP matchmaker_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE matchmaker_{} {}", name, kind);
+            let _ = writeln!(out, "matchmaker_{} {}", name, value.load(Ordering::Relaxed));
+        };
+
+        single(&mut out, "connected_clients", "gauge", "Clients currently connected", &self.connected_clients);
+        single(&mut out, "matches_total", "counter", "Matches made", &self.matches);
+        single(&mut out, "expired_sessions_total", "counter", "Sessions closed for reaching a lifetime limit", &self.expired_sessions);
+        single(&mut out, "retransmits_total", "counter", "Unacknowledged packets sent again", &self.retransmits);
+        single(&mut out, "unknown_packets_total", "counter", "Datagrams that could not be parsed", &self.unknown_packets);
+        single(&mut out, "tick_overruns_total", "counter", "Ticks skipped because the server was running behind", &self.tick_overruns);
+
+        let _ = writeln!(out, "# HELP matchmaker_open_sessions Sessions waiting for an opponent");
+        let _ = writeln!(out, "# TYPE matchmaker_open_sessions gauge");
+        let _ = writeln!(out, "matchmaker_open_sessions{{visibility=\"public\"}} {}", self.public_sessions.load(Ordering::Relaxed));
+        let _ = writeln!(out, "matchmaker_open_sessions{{visibility=\"private\"}} {}", self.private_sessions.load(Ordering::Relaxed));
+
+        let labelled = |out: &mut String, name: &str, help: &str, label: &str, names: &[&str], values: &[AtomicU64]| {
+            let _ = writeln!(out, "# HELP matchmaker_{} {}", name, help);
+            let _ = writeln!(out, "# TYPE matchmaker_{} counter", name);
+
+            for (label_value, value) in names.iter().zip(values.iter()) {
+                let _ = writeln!(out, "matchmaker_{}{{{}=\"{}\"}} {}", name, label, label_value, value.load(Ordering::Relaxed));
+            }
+        };
+
+        labelled(&mut out, "join_failures_total", "Join requests that did not make a match", "reason", &JOIN_FAILURE_REASONS, &self.join_failures);
+        labelled(&mut out, "packets_received_total", "Packets received by type", "type", &PACKET_TYPES, &self.packets_received);
+        labelled(&mut out, "packets_sent_total", "Packets sent by type, excluding retransmits", "type", &PACKET_TYPES, &self.packets_sent);
+
+        out
+    }
+}
@@ -1,2 +1,3 @@
+#[allow(clippy::module_inception)]
 mod packets;
 pub use packets::*;
\ No newline at end of file
@@ -1,6 +1,9 @@
-use std::net::{UdpSocket, SocketAddr};
-use crate::threads::clock_thread::TICK_RATE;
-use num_derive;
+use std::net::SocketAddr;
+use crate::crypto::TransportCipher;
+use crate::metrics::METRICS;
+use crate::runtime::{SharedClock, Transport};
+use log::trace;
+use num_traits::FromPrimitive;
 
 // enums
 #[derive(num_derive::FromPrimitive)]
@@ -10,7 +13,13 @@ enum PacketId {
     Create = 2,
     Join = 3,
     Close = 4,
-    Error = 5
+    Error = 5,
+    Challenge = 6,
+    Attest = 7,
+    KeyExchange = 8,
+    Notice = 9,
+    Resume = 10,
+    SessionExpired = 11
 }
 
 enum PacketType {
@@ -18,6 +27,27 @@ enum PacketType {
     DataPacket = 1
 }
 
+// Set on the packet type byte of server datagrams whose payload is sealed
+const SEALED_FLAG: u8 = 0x80;
+
+// Client datagrams use this packet id for a sealed payload
+const SEALED_PACKET_ID: u16 = u16::MAX;
+
+// Sent with every error so clients can react without parsing the message
+#[derive(Clone, Copy, Debug, PartialEq, Eq, num_derive::FromPrimitive)]
+pub enum ErrorCode {
+    // no specific code, or one this build doesn't know
+    Unknown = 0,
+    NotAttested = 1,
+    AttestationFailed = 2,
+    KeyExchangeFailed = 3,
+    SessionFailed = 4,
+    ShuttingDown = 5,
+    Maintenance = 6,
+    ResumeFailed = 7
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ServerPacket<'a> {
     Ping,
     Ack {
@@ -26,18 +56,40 @@ pub enum ServerPacket<'a> {
     Create {
         session_key: &'a str
     },
-    Join {
-        client_addr: Option<&'a SocketAddr>,
-        success: bool
+    // None if the join failed
+    Join(Option<JoinReply<'a>>),
+    Close {
+        reason: &'a str
     },
-    Close,
     Error {
         id: u32,
+        code: ErrorCode,
+        message: &'a str
+    },
+    Challenge {
+        nonce: &'a [u8]
+    },
+    Attest {
+        success: bool
+    },
+    KeyExchange {
+        public_key: &'a [u8],
+        // Ed25519 over the context, client key and server key, see `verify_key_exchange`
+        signature: &'a [u8]
+    },
+    Notice {
         message: &'a str
+    },
+    // The token to send in a client `Resume` from a new address
+    Resume {
+        token: &'a [u8]
+    },
+    SessionExpired {
+        session_key: &'a str
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ClientPacket {
     Pong,
     Ack {
@@ -51,7 +103,67 @@ pub enum ClientPacket {
         client_hash: String,
         session_key: String
     },
-    Close
+    Close,
+    Attest {
+        build: String,
+        mac: Vec<u8>
+    },
+    KeyExchange {
+        public_key: Vec<u8>
+    },
+    Resume {
+        token: Vec<u8>
+    },
+    // Still encrypted, opened with the client's transport cipher
+    Sealed {
+        data: Vec<u8>
+    }
+}
+
+// Sent to both peers of a match
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JoinReply<'a> {
+    // the other peer
+    pub client_addr: SocketAddr,
+    pub ticket: &'a [u8],
+    pub match_key: &'a [u8]
+}
+
+impl ServerPacket<'_> {
+    // Label used for metrics
+    pub fn name(&self) -> &'static str {
+        match self {
+            ServerPacket::Ping => "ping_pong",
+            ServerPacket::Ack { .. } => "ack",
+            ServerPacket::Create { .. } => "create",
+            ServerPacket::Join(_) => "join",
+            ServerPacket::Close { .. } => "close",
+            ServerPacket::Error { .. } => "error",
+            ServerPacket::Challenge { .. } => "challenge",
+            ServerPacket::Attest { .. } => "attest",
+            ServerPacket::KeyExchange { .. } => "key_exchange",
+            ServerPacket::Notice { .. } => "notice",
+            ServerPacket::Resume { .. } => "resume",
+            ServerPacket::SessionExpired { .. } => "session_expired"
+        }
+    }
+}
+
+impl ClientPacket {
+    // Label used for metrics
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClientPacket::Pong => "ping_pong",
+            ClientPacket::Ack { .. } => "ack",
+            ClientPacket::Create { .. } => "create",
+            ClientPacket::Join { .. } => "join",
+            ClientPacket::Close => "close",
+            ClientPacket::Attest { .. } => "attest",
+            ClientPacket::KeyExchange { .. } => "key_exchange",
+            ClientPacket::Resume { .. } => "resume",
+            ClientPacket::Sealed { .. } => "sealed"
+        }
+    }
 }
 
 // packets
@@ -66,49 +178,80 @@ pub struct Packet {
 
 pub struct PacketShipper {
     socket_address: SocketAddr,
+    retry_delay: std::time::Duration,
     next_id: u32,
-    backed_up: Vec<Packet>
+    backed_up: Vec<Packet>,
+    cipher: Option<TransportCipher>,
+    clock: SharedClock
 }
 
 impl PacketShipper {
-    pub fn new(socket_address: SocketAddr) -> PacketShipper {
+    pub fn new(socket_address: SocketAddr, retry_delay: std::time::Duration, clock: SharedClock) -> PacketShipper {
         PacketShipper {
             socket_address,
+            retry_delay,
             next_id: 0,
-            backed_up: Vec::new()
+            backed_up: Vec::new(),
+            cipher: None,
+            clock
         }
     }
 
-    pub fn send(&mut self, socket: &UdpSocket, packet: &ServerPacket) {
-        let mut data = vec![];
+    // Every packet sent from now on is sealed
+    pub fn seal_with(&mut self, cipher: TransportCipher) {
+        self.cipher = Some(cipher);
+    }
 
-        data.push(PacketType::DataPacket as u8);
+    // Unacknowledged packets are resent to the new address
+    pub fn set_socket_address(&mut self, socket_address: SocketAddr) {
+        self.socket_address = socket_address;
+    }
 
-        write_u32(&mut data, self.next_id);
-        data.extend(build_server_packet(packet));
+    pub fn send(&mut self, socket: &dyn Transport, packet: &ServerPacket) {
+        self.send_built(socket, packet.name(), &build_server_packet(packet));
+    }
 
-        println!("Buffer: {:?}", data);
+    // Sends a packet that was already built with `build_server_packet`
+    pub fn send_built(&mut self, socket: &dyn Transport, name: &'static str, payload: &[u8]) {
+        let mut data = vec![];
+        let packet_type = PacketType::DataPacket as u8;
+
+        match &self.cipher {
+            Some(cipher) => {
+                data.push(packet_type | SEALED_FLAG);
+                write_u32(&mut data, self.next_id);
+
+                let sealed = cipher.seal(packet_type, self.next_id, &data, payload);
+                data.extend(sealed);
+            },
+            None => {
+                data.push(packet_type);
+                write_u32(&mut data, self.next_id);
+                data.extend_from_slice(payload);
+            }
+        }
 
-        let _ = socket.send_to(&data, self.socket_address);
+        trace!(addr:% = self.socket_address, id = self.next_id, bytes:? = data; "Sending packet");
 
-        println!("After socket send to {} ", self.socket_address);
+        let _ = socket.send_to(&data, self.socket_address);
+        METRICS.packet_sent(name);
 
         self.backed_up.push(Packet {
             id: self.next_id,
-            creation_time: std::time::Instant::now(),
+            creation_time: self.clock.now(),
             data
         });
 
         self.next_id += 1;
     }
 
-    pub fn resend_unacknowledged_packets(&self, socket: &UdpSocket) {
-        let retry_delay = std::time::Duration::from_secs_f64(1.0 / TICK_RATE);
+    pub fn resend_unacknowledged_packets(&self, socket: &dyn Transport) {
+        let now = self.clock.now();
 
         let iter = self
             .backed_up
             .iter()
-            .take_while(|packet| packet.creation_time.elapsed() >= retry_delay);
+            .take_while(|packet| now.duration_since(packet.creation_time) >= self.retry_delay);
 
         for packet in iter {
             let buf = &packet.data;
@@ -117,9 +260,15 @@ impl PacketShipper {
                 // socket buffer is probably full
                 break;
             }
+
+            METRICS.retransmitted();
         }
     }
 
+    pub fn has_unacknowledged_packets(&self) -> bool {
+        !self.backed_up.is_empty()
+    }
+
     pub fn acknowledge(&mut self, id: u32) {
         self
         .backed_up
@@ -142,29 +291,47 @@ pub struct PacketReciever {
     socket_address: std::net::SocketAddr,
     next_id: u32,
     // backed_up: Vec<RecievedPacket>, 
-    last_message_time: std::time::Instant
+    last_message_time: std::time::Instant,
+    cipher: Option<TransportCipher>,
+    clock: SharedClock
 }
 
 impl PacketReciever {
-    pub fn new(socket_address: std::net::SocketAddr) -> PacketReciever {
+    pub fn new(socket_address: std::net::SocketAddr, clock: SharedClock) -> PacketReciever {
         PacketReciever {
             socket_address,
             next_id: 0,
             // backed_up: Vec::new(),
-            last_message_time: std::time::Instant::now()
+            last_message_time: clock.now(),
+            cipher: None,
+            clock
         }
     }
 
+    // Every ack sent from now on is sealed
+    pub fn seal_with(&mut self, cipher: TransportCipher) {
+        self.cipher = Some(cipher);
+    }
+
+    pub fn set_socket_address(&mut self, socket_address: SocketAddr) {
+        self.socket_address = socket_address;
+    }
+
+    // True if a packet with this id was already sorted, or was skipped by a later one
+    pub fn has_passed(&self, id: u32) -> bool {
+        id < self.next_id
+    }
+
     pub fn get_last_message_time(&self) -> &std::time::Instant {
         &self.last_message_time
     }
 
     pub fn sort_packets(&mut self,
-        socket: &UdpSocket,
+        socket: &dyn Transport,
         id: u32,
         packet: ClientPacket
     ) -> Option<ClientPacket> {
-        self.last_message_time = std::time::Instant::now();
+        self.last_message_time = self.clock.now();
 
         self.send_ack(socket, id);
 
@@ -172,26 +339,39 @@ impl PacketReciever {
             // ignore old packets
             None
         } else {
-            self.next_id = id + 1;
+            self.next_id = id.saturating_add(1);
             Some(packet)
         }
     }
 
-    fn send_ack(&self, socket: &UdpSocket, id: u32) {
+    fn send_ack(&self, socket: &dyn Transport, id: u32) {
         let mut data = vec![];
+        let packet_type = PacketType::AckPacket as u8;
 
-        data.push(PacketType::AckPacket as u8); // ack packet type
-
-        data.extend(
-            build_server_packet(
-                &ServerPacket::Ack {
-                    id
-                }
-            )
+        let ack = build_server_packet(
+            &ServerPacket::Ack {
+                id
+            }
         );
 
-        println!("Buffer: {:?}", data);
+        match &self.cipher {
+            Some(cipher) => {
+                // sealed acks carry the acknowledged id in the clear for the nonce
+                data.push(packet_type | SEALED_FLAG);
+                write_u32(&mut data, id);
+
+                let sealed = cipher.seal(packet_type, id, &data, &ack);
+                data.extend(sealed);
+            },
+            None => {
+                data.push(packet_type); // ack packet type
+                data.extend(ack);
+            }
+        }
+
+        trace!(addr:% = self.socket_address, id, bytes:? = data; "Sending ack");
         let _ = socket.send_to(&data, self.socket_address);
+        METRICS.packet_sent("ack");
     }
 }
 
@@ -244,54 +424,138 @@ pub fn read_u32(buf: &mut &[u8]) -> Option<u32> {
 }
 
 pub fn read_string_u8(buf: &mut &[u8]) -> Option<String> {
-    let len = read_byte(buf)? as usize;
-    read_string(buf, len)
+    read_str_u8(buf).map(String::from)
+}
+
+pub fn read_bytes_u8(buf: &mut &[u8]) -> Option<Vec<u8>> {
+    read_slice_u8(buf).map(<[u8]>::to_vec)
+}
+
+pub fn read_str_u8<'a>(buf: &mut &'a [u8]) -> Option<&'a str> {
+    std::str::from_utf8(read_slice_u8(buf)?).ok()
 }
 
-fn read_string(buf: &mut &[u8], len: usize) -> Option<String> {
+pub fn read_slice_u8<'a>(buf: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = read_byte(buf)? as usize;
+
     if buf.len() < len {
         *buf = &buf[buf.len()..];
         return None;
     }
 
-    let string_slice = std::str::from_utf8(&buf[..len]).ok();
-
-    *buf = &buf[len..];
+    let (bytes, rest) = buf.split_at(len);
+    *buf = rest;
 
-    let string = String::from(string_slice?);
-
-    Some(string)
+    Some(bytes)
 }
 
 fn parse_headers(buf: &mut &[u8]) -> Option<u32> {
-    Some(read_u32(buf)?)
-}
-
-fn parse_packet(buf: &mut &[u8]) -> Option<ClientPacket> {
-    let packet_type = read_u16(buf)?;
-
-    println!("packet_type: {}", packet_type);
-
-    match packet_type {
-        0 => Some(ClientPacket::Pong),
-        1 => Some(ClientPacket::Ack {
-            id: read_u32(buf)?
-        }),
-        2 => Some(ClientPacket::Create {
-            client_hash: read_string_u8(buf)?,
-            password_protected: read_bool(buf)?
-        }),
-        3 => Some(ClientPacket::Join{
-            client_hash: read_string_u8(buf)?,
-            session_key: read_string_u8(buf)?
-        }),
-        4 => Some(ClientPacket::Close),
-        _ => None
+    read_u32(buf)
+}
+
+impl<'a> Wire<'a> for ClientPacket {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            ClientPacket::Pong => {
+                write_u16(buf, PacketId::PingPong as u16);
+            },
+            ClientPacket::Ack { id } => {
+                write_u16(buf, PacketId::Ack as u16);
+                write_u32(buf, *id);
+            },
+            ClientPacket::Create { client_hash, password_protected } => {
+                write_u16(buf, PacketId::Create as u16);
+                write_string_u8(buf, client_hash);
+                write_bool(buf, *password_protected);
+            },
+            ClientPacket::Join { client_hash, session_key } => {
+                write_u16(buf, PacketId::Join as u16);
+                write_string_u8(buf, client_hash);
+                write_string_u8(buf, session_key);
+            },
+            ClientPacket::Close => {
+                write_u16(buf, PacketId::Close as u16);
+            },
+            ClientPacket::Attest { build, mac } => {
+                write_u16(buf, PacketId::Attest as u16);
+                write_string_u8(buf, build);
+                write_bytes_u8(buf, mac);
+            },
+            ClientPacket::KeyExchange { public_key } => {
+                write_u16(buf, PacketId::KeyExchange as u16);
+                write_bytes_u8(buf, public_key);
+            },
+            ClientPacket::Resume { token } => {
+                write_u16(buf, PacketId::Resume as u16);
+                write_bytes_u8(buf, token);
+            },
+            ClientPacket::Sealed { data } => {
+                // the rest of the datagram, no length
+                write_u16(buf, SEALED_PACKET_ID);
+                buf.extend(data);
+            }
+        }
+    }
+
+    fn decode(buf: &mut &'a [u8]) -> Option<ClientPacket> {
+        let packet_id = read_u16(buf)?;
+
+        trace!(packet_id; "Parsing packet");
+
+        if packet_id == SEALED_PACKET_ID {
+            let data = buf.to_vec();
+            *buf = &buf[buf.len()..];
+            return Some(ClientPacket::Sealed { data });
+        }
+
+        let packet = match PacketId::from_u16(packet_id)? {
+            PacketId::PingPong => ClientPacket::Pong,
+            PacketId::Ack => ClientPacket::Ack {
+                id: read_u32(buf)?
+            },
+            PacketId::Create => ClientPacket::Create {
+                client_hash: read_string_u8(buf)?,
+                password_protected: read_bool(buf)?
+            },
+            PacketId::Join => ClientPacket::Join {
+                client_hash: read_string_u8(buf)?,
+                session_key: read_string_u8(buf)?
+            },
+            PacketId::Close => ClientPacket::Close,
+            PacketId::Attest => ClientPacket::Attest {
+                build: read_string_u8(buf)?,
+                mac: read_bytes_u8(buf)?
+            },
+            PacketId::KeyExchange => ClientPacket::KeyExchange {
+                public_key: read_bytes_u8(buf)?
+            },
+            PacketId::Resume => ClientPacket::Resume {
+                token: read_bytes_u8(buf)?
+            },
+            // only sent by the server
+            PacketId::Error | PacketId::Challenge | PacketId::Notice | PacketId::SessionExpired => return None
+        };
+
+        Some(packet)
     }
 }
 
 pub fn parse_client_packet(mut buf: &[u8]) -> Option<(u32, ClientPacket)> {
-    Some((parse_headers(&mut buf)?, parse_packet(&mut buf)?))
+    Some((parse_headers(&mut buf)?, ClientPacket::decode(&mut buf)?))
+}
+
+// Decrypts the payload of a `ClientPacket::Sealed` and parses the packet inside
+pub fn open_client_packet(cipher: &TransportCipher, id: u32, data: &[u8]) -> Option<ClientPacket> {
+    let mut header = vec![];
+    write_u32(&mut header, id);
+    write_u16(&mut header, SEALED_PACKET_ID);
+
+    let plaintext = cipher.open(PacketType::DataPacket as u8, id, &header, data)?;
+
+    match ClientPacket::decode(&mut plaintext.as_slice())? {
+        ClientPacket::Sealed { .. } => None,
+        packet => Some(packet)
+    }
 }
 
 // writers
@@ -317,50 +581,151 @@ pub fn write_u32(buf: &mut Vec<u8>, data: u32) {
     buf.extend(&buf_32);
 }
 
+// Strings over 255 bytes are cut at the last whole character that fits
 pub fn write_string_u8(buf: &mut Vec<u8>, data: &str) {
-    let len = if data.len() < u8::MAX.into() {
-        data.len() as u8
-    } else {
-        u8::MAX
-    };
+    let mut len = data.len().min(u8::MAX.into());
+
+    while !data.is_char_boundary(len) {
+        len -= 1;
+    }
 
-    buf.push(len);
-    buf.extend(&data.as_bytes()[0..len.into()]);
+    buf.push(len as u8);
+    buf.extend(&data.as_bytes()[0..len]);
+}
+
+pub fn write_bytes_u8(buf: &mut Vec<u8>, data: &[u8]) {
+    let len = data.len().min(u8::MAX.into());
+
+    buf.push(len as u8);
+    buf.extend(&data[0..len]);
 }
 
 pub fn build_server_packet(packet: &ServerPacket) -> Vec<u8> {
-    let mut vec = Vec::new();
-    let buf = &mut vec;
-
-    match packet {
-        ServerPacket::Ping => {
-            write_u16(buf, PacketId::PingPong as u16);
-        },
-        ServerPacket::Ack { id } => {
-            write_u16(buf, PacketId::Ack as u16);
-            write_u32(buf, *id);
-        },
-        ServerPacket::Create { session_key } => {
-            write_u16(buf, PacketId::Create as u16);
-            write_string_u8(buf, *session_key);
-        },
-        ServerPacket::Join { client_addr, success } => {
-            write_u16(buf, PacketId::Join as u16);
-            write_bool(buf, *success);
-
-            if *success {
-                write_string_u8(buf, &client_addr.unwrap().to_string());
+    let mut buf = Vec::new();
+    packet.encode(&mut buf);
+    buf
+}
+
+// `[packet u16][fields]`, the same layout in both directions. Decoding borrows
+// strings and bytes from the buffer where the packet allows it.
+pub trait Wire<'a>: Sized {
+    fn encode(&self, buf: &mut Vec<u8>);
+    fn decode(buf: &mut &'a [u8]) -> Option<Self>;
+}
+
+impl<'a> Wire<'a> for ServerPacket<'a> {
+    fn encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            ServerPacket::Ping => {
+                write_u16(buf, PacketId::PingPong as u16);
+            },
+            ServerPacket::Ack { id } => {
+                write_u16(buf, PacketId::Ack as u16);
+                write_u32(buf, *id);
+            },
+            ServerPacket::Create { session_key } => {
+                write_u16(buf, PacketId::Create as u16);
+                write_string_u8(buf, session_key);
+            },
+            ServerPacket::Join(reply) => {
+                write_u16(buf, PacketId::Join as u16);
+                write_bool(buf, reply.is_some());
+
+                if let Some(reply) = reply {
+                    write_string_u8(buf, &reply.client_addr.to_string());
+                    write_bytes_u8(buf, reply.ticket);
+                    write_bytes_u8(buf, reply.match_key);
+                }
+            },
+            ServerPacket::Close { reason } => {
+                write_u16(buf, PacketId::Close as u16);
+                write_string_u8(buf, reason);
+            },
+            ServerPacket::Error { id, code, message } => {
+                write_u16(buf, PacketId::Error as u16);
+                write_u32(buf, *id);
+                write_string_u8(buf, message);
+                // after the message so older clients can still read it
+                write_u16(buf, *code as u16);
+            },
+            ServerPacket::Challenge { nonce } => {
+                write_u16(buf, PacketId::Challenge as u16);
+                write_bytes_u8(buf, nonce);
+            },
+            ServerPacket::Attest { success } => {
+                write_u16(buf, PacketId::Attest as u16);
+                write_bool(buf, *success);
+            },
+            ServerPacket::KeyExchange { public_key, signature } => {
+                write_u16(buf, PacketId::KeyExchange as u16);
+                write_bytes_u8(buf, public_key);
+                write_bytes_u8(buf, signature);
+            },
+            ServerPacket::Notice { message } => {
+                write_u16(buf, PacketId::Notice as u16);
+                write_string_u8(buf, message);
+            },
+            ServerPacket::Resume { token } => {
+                write_u16(buf, PacketId::Resume as u16);
+                write_bytes_u8(buf, token);
+            },
+            ServerPacket::SessionExpired { session_key } => {
+                write_u16(buf, PacketId::SessionExpired as u16);
+                write_string_u8(buf, session_key);
             }
-        },
-        ServerPacket::Close => {
-            write_u16(buf, PacketId::Close as u16);
-        },
-        ServerPacket::Error { id, message } => {
-            write_u16(buf, PacketId::Error as u16);
-            write_u32(buf, *id);
-            write_string_u8(buf, *message);
         }
     }
 
-    vec
-}
\ No newline at end of file
+    fn decode(buf: &mut &'a [u8]) -> Option<ServerPacket<'a>> {
+        let packet = match PacketId::from_u16(read_u16(buf)?)? {
+            PacketId::PingPong => ServerPacket::Ping,
+            PacketId::Ack => ServerPacket::Ack {
+                id: read_u32(buf)?
+            },
+            PacketId::Create => ServerPacket::Create {
+                session_key: read_str_u8(buf)?
+            },
+            PacketId::Join => {
+                if read_bool(buf)? {
+                    ServerPacket::Join(Some(JoinReply {
+                        client_addr: read_str_u8(buf)?.parse().ok()?,
+                        ticket: read_slice_u8(buf)?,
+                        match_key: read_slice_u8(buf)?
+                    }))
+                } else {
+                    ServerPacket::Join(None)
+                }
+            },
+            PacketId::Close => ServerPacket::Close {
+                reason: read_str_u8(buf)?
+            },
+            PacketId::Error => ServerPacket::Error {
+                id: read_u32(buf)?,
+                message: read_str_u8(buf)?,
+                // missing from older servers
+                code: read_u16(buf).and_then(ErrorCode::from_u16).unwrap_or(ErrorCode::Unknown)
+            },
+            PacketId::Challenge => ServerPacket::Challenge {
+                nonce: read_slice_u8(buf)?
+            },
+            PacketId::Attest => ServerPacket::Attest {
+                success: read_bool(buf)?
+            },
+            PacketId::KeyExchange => ServerPacket::KeyExchange {
+                public_key: read_slice_u8(buf)?,
+                signature: read_slice_u8(buf)?
+            },
+            PacketId::Notice => ServerPacket::Notice {
+                message: read_str_u8(buf)?
+            },
+            PacketId::Resume => ServerPacket::Resume {
+                token: read_slice_u8(buf)?
+            },
+            PacketId::SessionExpired => ServerPacket::SessionExpired {
+                session_key: read_str_u8(buf)?
+            }
+        };
+
+        Some(packet)
+    }
+}
@@ -0,0 +1,2 @@
+mod session_snapshot;
+pub use session_snapshot::{SessionRecord, SessionSnapshot};
\ No newline at end of file
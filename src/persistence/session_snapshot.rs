@@ -0,0 +1,120 @@
+use crate::encoding::{decode_hex, encode_hex};
+use crate::tickets::ResumeToken;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const SNAPSHOT_VERSION: u8 = 1;
+
+// An open session as written to disk
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct SessionRecord {
+    pub key: String,
+    pub host: SocketAddr,
+    pub password_protected: bool,
+    // unix seconds
+    pub created: u64,
+    // hex, lets the host resume from a new address
+    #[serde(default)]
+    pub resume_token: String
+}
+
+impl SessionRecord {
+    pub fn new(key: &str, host: SocketAddr, password_protected: bool, age: Duration, resume_token: &ResumeToken) -> SessionRecord {
+        let created = SystemTime::now()
+            .checked_sub(age)
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|since_epoch| since_epoch.as_secs())
+            .unwrap_or(0);
+
+        SessionRecord {
+            key: key.to_string(),
+            host,
+            password_protected,
+            created,
+            resume_token: encode_hex(resume_token)
+        }
+    }
+
+    pub fn resume_token(&self) -> Option<ResumeToken> {
+        ResumeToken::try_from(decode_hex(&self.resume_token)?.as_slice()).ok()
+    }
+
+    // The record's creation time on this run's clock
+    pub fn created_at(&self, now: Instant) -> Instant {
+        let created = UNIX_EPOCH + Duration::from_secs(self.created);
+        let age = SystemTime::now().duration_since(created).unwrap_or_default();
+
+        now.checked_sub(age).unwrap_or(now)
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, PartialEq)]
+pub struct SessionSnapshot {
+    version: u8,
+    pub sessions: Vec<SessionRecord>
+}
+
+impl SessionSnapshot {
+    pub fn new(sessions: Vec<SessionRecord>) -> SessionSnapshot {
+        SessionSnapshot {
+            version: SNAPSHOT_VERSION,
+            sessions
+        }
+    }
+
+    // A missing file is an empty snapshot
+    pub fn load(path: &str) -> Result<SessionSnapshot, String> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(SessionSnapshot::default()),
+            Err(e) => return Err(format!("{}: {}", path, e))
+        };
+
+        let snapshot: SessionSnapshot = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))?;
+
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(format!("{}: unsupported snapshot version {}", path, snapshot.version));
+        }
+
+        Ok(snapshot)
+    }
+
+    // Written to a temporary file first so a crash never leaves half a snapshot.
+    // Resume tokens are secrets, so the file is only readable by its owner.
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        let temporary_path = format!("{}.tmp", path);
+
+        create_private(&temporary_path)
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .map_err(|e| format!("{}: {}", temporary_path, e))?;
+
+        std::fs::rename(&temporary_path, path).map_err(|e| format!("{}: {}", path, e))
+    }
+}
+
+// A leftover file from a crash is replaced rather than reusing its permissions
+#[cfg(unix)]
+fn create_private(path: &str) -> std::io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    remove_leftover(path)?;
+    OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &str) -> std::io::Result<File> {
+    remove_leftover(path)?;
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+fn remove_leftover(path: &str) -> std::io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(())
+    }
+}
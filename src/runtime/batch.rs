@@ -0,0 +1,297 @@
+use async_io::Async;
+use std::cell::RefCell;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+
+use super::Transport;
+
+// Most datagrams read or written by one syscall
+pub const BATCH_SIZE: usize = 32;
+
+// Datagrams read from a socket, in buffers that are reused by every read.
+// On Linux one read takes as many as are waiting, up to `BATCH_SIZE`.
+pub struct Datagrams {
+    buffers: Vec<Vec<u8>>,
+    lengths: Vec<usize>,
+    addresses: Vec<SocketAddr>,
+    count: usize
+}
+
+impl Datagrams {
+    // Datagrams longer than `buffer_size` are left empty on Linux and cut short elsewhere
+    pub fn new(buffer_size: usize) -> Datagrams {
+        Datagrams {
+            buffers: vec![vec![0; buffer_size]; BATCH_SIZE],
+            lengths: vec![0; BATCH_SIZE],
+            addresses: vec![SocketAddr::from(([0, 0, 0, 0], 0)); BATCH_SIZE],
+            count: 0
+        }
+    }
+
+    // Waits until the socket is readable and reads what is there, returns how many were read
+    pub async fn receive(&mut self, socket: &Async<UdpSocket>) -> io::Result<usize> {
+        let Datagrams { buffers, lengths, addresses, count } = self;
+
+        *count = 0;
+        *count = socket.read_with(|socket| sys::receive(socket, buffers, lengths, addresses)).await?;
+
+        Ok(*count)
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    // The datagrams from the last read, in the order they arrived
+    pub fn iter(&self) -> impl Iterator<Item = (SocketAddr, &[u8])> {
+        (0..self.count).map(move |index| (self.addresses[index], &self.buffers[index][..self.lengths[index]]))
+    }
+}
+
+// Queues datagrams and writes them with as few syscalls as the OS allows.
+// Nothing is sent until the queue fills up or `flush` is called.
+pub struct BatchSender {
+    socket: UdpSocket,
+    queue: RefCell<Queue>
+}
+
+#[derive(Default)]
+struct Queue {
+    // kept between flushes so their allocations are reused
+    buffers: Vec<Vec<u8>>,
+    addresses: Vec<SocketAddr>,
+    len: usize
+}
+
+impl BatchSender {
+    pub fn new(socket: UdpSocket) -> BatchSender {
+        BatchSender {
+            socket,
+            queue: RefCell::new(Queue::default())
+        }
+    }
+
+    // Sends everything queued. A full socket buffer drops what is left,
+    // like a plain send would, and reliable packets are resent later.
+    pub fn flush(&self) -> io::Result<()> {
+        let mut queue = self.queue.borrow_mut();
+        let Queue { buffers, addresses, len } = &mut *queue;
+
+        let mut sent = 0;
+        let mut result = Ok(());
+
+        while sent < *len {
+            match sys::send(&self.socket, &buffers[sent..*len], &addresses[sent..*len]) {
+                Ok(count) => sent += count,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    result = Err(e);
+                    break;
+                },
+                // one unreachable address doesn't hold up the rest
+                Err(e) => {
+                    sent += 1;
+                    result = Err(e);
+                }
+            }
+        }
+
+        *len = 0;
+
+        result
+    }
+}
+
+impl Transport for BatchSender {
+    fn send_to(&self, data: &[u8], address: SocketAddr) -> io::Result<usize> {
+        if self.queue.borrow().len == BATCH_SIZE {
+            self.flush()?;
+        }
+
+        let mut queue = self.queue.borrow_mut();
+        let index = queue.len;
+
+        if index == queue.buffers.len() {
+            queue.buffers.push(Vec::new());
+            queue.addresses.push(address);
+        }
+
+        queue.buffers[index].clear();
+        queue.buffers[index].extend_from_slice(data);
+        queue.addresses[index] = address;
+        queue.len += 1;
+
+        Ok(data.len())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::io;
+    use std::mem;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket};
+    use std::os::unix::io::AsRawFd;
+
+    use super::BATCH_SIZE;
+
+    // One recvmmsg for everything waiting, up to a buffer each
+    pub fn receive(socket: &UdpSocket, buffers: &mut [Vec<u8>], lengths: &mut [usize], addresses: &mut [SocketAddr]) -> io::Result<usize> {
+        let count = buffers.len().min(BATCH_SIZE);
+
+        // SAFETY: iovec, sockaddr_storage and mmsghdr are plain C structs, all
+        // zeroes is a valid value for each (null pointers and zero lengths)
+        let mut iovecs: [libc::iovec; BATCH_SIZE] = unsafe { mem::zeroed() };
+        let mut names: [libc::sockaddr_storage; BATCH_SIZE] = unsafe { mem::zeroed() };
+        let mut headers: [libc::mmsghdr; BATCH_SIZE] = unsafe { mem::zeroed() };
+
+        for index in 0..count {
+            iovecs[index].iov_base = buffers[index].as_mut_ptr() as *mut libc::c_void;
+            iovecs[index].iov_len = buffers[index].len();
+
+            headers[index].msg_hdr.msg_name = &mut names[index] as *mut libc::sockaddr_storage as *mut libc::c_void;
+            headers[index].msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+            headers[index].msg_hdr.msg_iov = &mut iovecs[index];
+            headers[index].msg_hdr.msg_iovlen = 1;
+        }
+
+        // SAFETY: the first `count` headers point at iovecs and names in this
+        // frame, which outlive the call, and each iovec covers exactly its
+        // buffer (`iov_len` is the buffer's length), which stays borrowed
+        // mutably until we return. The kernel writes at most `count` entries.
+        let received = unsafe {
+            libc::recvmmsg(socket.as_raw_fd(), headers.as_mut_ptr(), count as _, libc::MSG_DONTWAIT as _, std::ptr::null_mut())
+        };
+
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // only the entries the kernel filled in are read
+        let received = received as usize;
+
+        for index in 0..received {
+            let truncated = headers[index].msg_hdr.msg_flags & libc::MSG_TRUNC != 0;
+
+            match to_socket_addr(&names[index]) {
+                Some(address) => {
+                    addresses[index] = address;
+
+                    // longer than its buffer, left empty so it is dropped as unknown rather than read in part
+                    lengths[index] = if truncated { 0 } else { (headers[index].msg_len as usize).min(buffers[index].len()) };
+                },
+                // not something an IP socket receives, left empty so it is dropped as unknown
+                None => lengths[index] = 0
+            }
+        }
+
+        Ok(received)
+    }
+
+    // One sendmmsg for as many as the socket takes, returns how many it took
+    pub fn send(socket: &UdpSocket, buffers: &[Vec<u8>], addresses: &[SocketAddr]) -> io::Result<usize> {
+        let count = buffers.len().min(BATCH_SIZE);
+
+        // SAFETY: plain C structs, all zeroes is a valid value for each
+        let mut iovecs: [libc::iovec; BATCH_SIZE] = unsafe { mem::zeroed() };
+        let mut names: [libc::sockaddr_storage; BATCH_SIZE] = unsafe { mem::zeroed() };
+        let mut headers: [libc::mmsghdr; BATCH_SIZE] = unsafe { mem::zeroed() };
+
+        for index in 0..count {
+            // sendmmsg only reads through the pointer, the cast to *mut is what iovec asks for
+            iovecs[index].iov_base = buffers[index].as_ptr() as *mut libc::c_void;
+            iovecs[index].iov_len = buffers[index].len();
+
+            headers[index].msg_hdr.msg_name = &mut names[index] as *mut libc::sockaddr_storage as *mut libc::c_void;
+            headers[index].msg_hdr.msg_namelen = from_socket_addr(&addresses[index], &mut names[index]);
+            headers[index].msg_hdr.msg_iov = &mut iovecs[index];
+            headers[index].msg_hdr.msg_iovlen = 1;
+        }
+
+        // SAFETY: the first `count` headers point at iovecs and names in this
+        // frame and at the caller's buffers, all of which outlive the call.
+        // Each `iov_len` is its buffer's length and each `msg_namelen` the
+        // size of the address written into its name.
+        let sent = unsafe {
+            libc::sendmmsg(socket.as_raw_fd(), headers.as_mut_ptr(), count as _, libc::MSG_DONTWAIT as _)
+        };
+
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(sent as usize)
+    }
+
+    fn to_socket_addr(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+        match storage.ss_family as libc::c_int {
+            libc::AF_INET => {
+                // SAFETY: the family says the storage holds a sockaddr_in, which
+                // is smaller than sockaddr_storage and needs no more alignment
+                let address = unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in) };
+                let ip = Ipv4Addr::from(u32::from_be(address.sin_addr.s_addr));
+
+                Some(SocketAddr::V4(SocketAddrV4::new(ip, u16::from_be(address.sin_port))))
+            },
+            libc::AF_INET6 => {
+                // SAFETY: as above, for sockaddr_in6
+                let address = unsafe { &*(storage as *const libc::sockaddr_storage as *const libc::sockaddr_in6) };
+                let ip = Ipv6Addr::from(address.sin6_addr.s6_addr);
+
+                Some(SocketAddr::V6(SocketAddrV6::new(ip, u16::from_be(address.sin6_port), address.sin6_flowinfo, address.sin6_scope_id)))
+            },
+            _ => None
+        }
+    }
+
+    // Fills in `storage`, returns the length of the part that was used
+    fn from_socket_addr(address: &SocketAddr, storage: &mut libc::sockaddr_storage) -> libc::socklen_t {
+        match address {
+            SocketAddr::V4(address) => {
+                // SAFETY: sockaddr_storage is large and aligned enough for any
+                // address type, and it is only read back as the family set here
+                let name = unsafe { &mut *(storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in) };
+                name.sin_family = libc::AF_INET as libc::sa_family_t;
+                name.sin_port = address.port().to_be();
+                name.sin_addr.s_addr = u32::from(*address.ip()).to_be();
+
+                mem::size_of::<libc::sockaddr_in>() as libc::socklen_t
+            },
+            SocketAddr::V6(address) => {
+                // SAFETY: as above, for sockaddr_in6
+                let name = unsafe { &mut *(storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in6) };
+                name.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                name.sin6_port = address.port().to_be();
+                name.sin6_flowinfo = address.flowinfo();
+                name.sin6_addr.s6_addr = address.ip().octets();
+                name.sin6_scope_id = address.scope_id();
+
+                mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t
+            }
+        }
+    }
+}
+
+// One datagram per syscall, as before batching. Truncation isn't reported
+// here, long datagrams are cut to the buffer (or fail the read on Windows).
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::io;
+    use std::net::{SocketAddr, UdpSocket};
+
+    pub fn receive(socket: &UdpSocket, buffers: &mut [Vec<u8>], lengths: &mut [usize], addresses: &mut [SocketAddr]) -> io::Result<usize> {
+        let (length, address) = socket.recv_from(&mut buffers[0])?;
+
+        lengths[0] = length;
+        addresses[0] = address;
+
+        Ok(1)
+    }
+
+    pub fn send(socket: &UdpSocket, buffers: &[Vec<u8>], addresses: &[SocketAddr]) -> io::Result<usize> {
+        socket.send_to(&buffers[0], addresses[0])?;
+        Ok(1)
+    }
+}
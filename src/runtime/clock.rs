@@ -0,0 +1,54 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Where the server reads the time, so tests and simulations can control it
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+pub type SharedClock = Arc<dyn Clock + Send + Sync>;
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+// Starts at the real time it was created and only moves when advanced
+pub struct VirtualClock {
+    start: Instant,
+    elapsed_nanos: AtomicU64
+}
+
+impl VirtualClock {
+    pub fn new() -> VirtualClock {
+        VirtualClock {
+            start: Instant::now(),
+            elapsed_nanos: AtomicU64::new(0)
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.elapsed_nanos.fetch_add(duration.as_nanos() as u64, Ordering::SeqCst);
+    }
+
+    // Time since the clock was created
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.elapsed_nanos.load(Ordering::SeqCst))
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> VirtualClock {
+        VirtualClock::new()
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+}
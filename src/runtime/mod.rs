@@ -0,0 +1,8 @@
+mod clock;
+pub use clock::{Clock, SharedClock, SystemClock, VirtualClock};
+
+mod transport;
+pub use transport::Transport;
+
+mod batch;
+pub use batch::{BatchSender, Datagrams, BATCH_SIZE};
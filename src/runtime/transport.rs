@@ -0,0 +1,13 @@
+use std::net::{SocketAddr, UdpSocket};
+
+// Where the server sends datagrams. Receiving happens in the server or
+// listening task, or wherever a simulation delivers packets from.
+pub trait Transport {
+    fn send_to(&self, data: &[u8], address: SocketAddr) -> std::io::Result<usize>;
+}
+
+impl Transport for UdpSocket {
+    fn send_to(&self, data: &[u8], address: SocketAddr) -> std::io::Result<usize> {
+        UdpSocket::send_to(self, data, address)
+    }
+}
@@ -0,0 +1,54 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::net::SocketAddr;
+use std::time::Instant;
+
+// When to look at each client next. Rescheduling a client leaves its old
+// entry in the queue, which is skipped once it comes up.
+#[derive(Default)]
+pub struct Deadlines {
+    queue: BinaryHeap<Reverse<(Instant, SocketAddr)>>,
+    scheduled: HashMap<SocketAddr, Instant>
+}
+
+impl Deadlines {
+    pub fn new() -> Deadlines {
+        Deadlines::default()
+    }
+
+    pub fn schedule(&mut self, socket_address: SocketAddr, at: Instant) {
+        self.scheduled.insert(socket_address, at);
+        self.queue.push(Reverse((at, socket_address)));
+    }
+
+    pub fn cancel(&mut self, socket_address: &SocketAddr) {
+        self.scheduled.remove(socket_address);
+    }
+
+    pub fn next(&mut self) -> Option<Instant> {
+        self.skip_stale();
+        self.queue.peek().map(|Reverse((at, _))| *at)
+    }
+
+    // Takes a client whose deadline has passed, if there is one
+    pub fn pop_due(&mut self, now: Instant) -> Option<SocketAddr> {
+        match self.next() {
+            Some(at) if at <= now => {
+                let Reverse((_, socket_address)) = self.queue.pop()?;
+                self.scheduled.remove(&socket_address);
+                Some(socket_address)
+            },
+            _ => None
+        }
+    }
+
+    fn skip_stale(&mut self) {
+        while let Some(Reverse((at, socket_address))) = self.queue.peek() {
+            if self.scheduled.get(socket_address) == Some(at) {
+                break;
+            }
+
+            self.queue.pop();
+        }
+    }
+}
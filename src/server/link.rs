@@ -0,0 +1,97 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::crypto::{TransportCipher, TransportKeys};
+use crate::packets::{PacketShipper, PacketReciever, ClientPacket, ServerPacket, ErrorCode, open_client_packet};
+use crate::runtime::{self, SharedClock};
+use crate::tickets::TicketSigner;
+
+// Set once the client completes a key exchange
+pub struct Transport {
+    pub from_client: TransportCipher,
+    pub sealed_from_id: u32
+}
+
+// The packet state of one client. It belongs to the server, or to the shard
+// task that handles the client's packets once the server hands it over.
+pub struct Link {
+    pub reciever: PacketReciever,
+    pub shipper: PacketShipper,
+    pub transport: Option<Transport>
+}
+
+impl Link {
+    pub fn new(socket_address: SocketAddr, retry_delay: Duration, clock: &SharedClock) -> Link {
+        Link {
+            reciever: PacketReciever::new(socket_address, clock.clone()),
+            shipper: PacketShipper::new(socket_address, retry_delay, clock.clone()),
+            transport: None
+        }
+    }
+
+    // Opens sealed packets. After the key exchange only packets the client
+    // sent before it (resends) may still arrive in plaintext.
+    fn unseal(&self, id: u32, packet: ClientPacket) -> Option<ClientPacket> {
+        match (&self.transport, packet) {
+            (Some(transport), ClientPacket::Sealed { data }) => open_client_packet(&transport.from_client, id, &data),
+            (Some(transport), packet) if id < transport.sealed_from_id => Some(packet),
+            (Some(_), _) => None,
+            (None, ClientPacket::Sealed { .. }) => None,
+            (None, packet) => Some(packet)
+        }
+    }
+
+    // Unseals and acks a packet, None if it can't be opened or is out of order
+    pub fn receive(&mut self, socket: &dyn runtime::Transport, id: u32, packet: ClientPacket) -> Option<ClientPacket> {
+        let packet = self.unseal(id, packet)?;
+        self.reciever.sort_packets(socket, id, packet)
+    }
+
+    // Answers a key exchange and seals everything sent and received after it.
+    // Returns true if the link switched to encrypted transport.
+    pub fn exchange_keys(&mut self, socket: &dyn runtime::Transport, id: u32, public_key: &[u8], ticket_signer: &TicketSigner) -> bool {
+        if self.transport.is_some() {
+            return false;
+        }
+
+        match TransportKeys::exchange(public_key) {
+            Some(keys) => {
+                let signature = ticket_signer.sign_key_exchange(public_key, &keys.public_key);
+
+                // the reply itself is the last plaintext packet
+                self.shipper.send(socket, &ServerPacket::KeyExchange { public_key: &keys.public_key, signature: &signature });
+                self.shipper.seal_with(keys.to_client.clone());
+                self.reciever.seal_with(keys.to_client);
+
+                self.transport = Some(Transport {
+                    from_client: keys.from_client,
+                    sealed_from_id: id + 1
+                });
+
+                true
+            },
+            None => {
+                self.shipper.send(socket, &ServerPacket::Error { id, code: ErrorCode::KeyExchangeFailed, message: "Key exchange failed" });
+                false
+            }
+        }
+    }
+
+    pub fn set_socket_address(&mut self, socket_address: SocketAddr) {
+        self.reciever.set_socket_address(socket_address);
+        self.shipper.set_socket_address(socket_address);
+    }
+
+    // Pings if due and resends unacknowledged packets
+    pub fn tick(&mut self, socket: &dyn runtime::Transport, ping: bool) {
+        if ping {
+            self.shipper.send(socket, &ServerPacket::Ping);
+        }
+
+        self.shipper.resend_unacknowledged_packets(socket);
+    }
+
+    pub fn last_message_time(&self) -> Instant {
+        *self.reciever.get_last_message_time()
+    }
+}
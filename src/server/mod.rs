@@ -0,0 +1,8 @@
+mod deadlines;
+mod link;
+pub(crate) use link::Link;
+
+#[allow(clippy::module_inception)]
+mod server;
+mod sessions;
+pub use server::{Server, file_read_lines};
@@ -0,0 +1,1570 @@
+use rand::{distributions::Alphanumeric, Rng};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::net::{UdpSocket, SocketAddr};
+use std::sync::Arc;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use async_io::Async;
+use async_std::channel::{self, Receiver, Sender};
+use futures_lite::future;
+
+use crate::access::{AccessList, IpRange};
+use crate::attestation::{BuildSecrets, generate_nonce, NONCE_LEN};
+use crate::config::{ServerConfig, RESTART_KEYS};
+use crate::logging;
+use log::{debug, error, info, warn};
+use crate::metrics::{METRICS, JoinFailure};
+use crate::persistence::{SessionRecord, SessionSnapshot};
+use crate::runtime::{self, BatchSender, Datagrams, SharedClock, SystemClock};
+use crate::packets::{ClientPacket, ServerPacket, ErrorCode, JoinReply, build_server_packet, parse_client_packet};
+use crate::tickets::{MatchTicket, TicketSigner, ResumeToken, generate_match_key, generate_resume_token};
+use super::deadlines::Deadlines;
+use super::link::Link;
+use super::sessions::{Session, Sessions};
+use crate::threads::{spawn_listening_task, spawn_watch_task, spawn_console_task, create_signal_thread, spawn_metrics_task, spawn_admin_task, spawn_shard_task, shard_for, ShardMessage, ThreadMessage};
+
+// Who has a client's packet state
+enum ClientLink {
+    // the server, if it has no shards or hasn't handed the client over yet
+    Local(Box<Link>),
+    // the shard with this index, which is sent every packet for the client
+    Shard(usize)
+}
+
+struct Client {
+    link: ClientLink,
+    encrypted: bool,
+    challenge: Option<[u8; NONCE_LEN]>,
+    attested_build: Option<String>,
+    connected_at: Instant,
+    // last packet other than a pong or ack
+    last_active: Instant,
+    resume_token: ResumeToken
+}
+
+impl Client {
+    fn new(socket_address: SocketAddr, retry_delay: Duration, clock: &SharedClock) -> Client {
+        let now = clock.now();
+
+        Client {
+            link: ClientLink::Local(Box::new(Link::new(socket_address, retry_delay, clock))),
+            encrypted: false,
+            challenge: None,
+            attested_build: None,
+            connected_at: now,
+            last_active: now,
+            resume_token: generate_resume_token()
+        }
+    }
+
+    // Reliably sends a packet, through the shard that has the link if there is one
+    fn send(&mut self, socket: &dyn runtime::Transport, shards: &[Sender<ShardMessage>], socket_address: &SocketAddr, packet: &ServerPacket) {
+        match &mut self.link {
+            ClientLink::Local(link) => link.shipper.send(socket, packet),
+            ClientLink::Shard(index) => {
+                let _ = shards[*index].try_send(ShardMessage::Send {
+                    socket_address: *socket_address,
+                    name: packet.name(),
+                    payload: build_server_packet(packet)
+                });
+            }
+        }
+    }
+}
+
+// What a shard reported after its last tick
+#[derive(Clone, Copy, Default)]
+struct ShardStatus {
+    tick: u64,
+    // clients with packets they haven't acked
+    unacknowledged: usize
+}
+
+enum Shutdown {
+    // refusing new sessions until open ones are matched or time runs out
+    Draining { until: Instant },
+    // every client was sent a close packet, waiting for their acks. Shards
+    // report on them in the first tick after `after_tick`.
+    Closing { until: Instant, after_tick: u64 }
+}
+
+// What woke the server loop
+enum Event {
+    Message(ThreadMessage),
+    // read into the server's `Datagrams`
+    Datagrams,
+    ReadFailed,
+    Deadline,
+    // every sender is gone
+    Closed
+}
+
+pub struct Server {
+    config: ServerConfig,
+    config_args: Vec<String>,
+    clients: HashMap<SocketAddr, Client>,
+    sessions: Sessions,
+    resume_tokens: HashMap<ResumeToken, SocketAddr>,
+    valid_client_hashes: Vec<String>,
+    build_secrets: BuildSecrets,
+    ticket_signer: Arc<TicketSigner>,
+    access_list: AccessList,
+    shutdown: Option<Shutdown>,
+    // refusing new sessions and joins, toggled by an admin
+    maintenance: bool,
+    // last snapshot written to `sessions_path`
+    saved_snapshot: SessionSnapshot,
+    last_snapshot_time: Instant,
+    last_ping_pong: Instant,
+    // when each client goes silent for too long
+    silence_deadlines: Deadlines,
+    // when each session expires, checked again before it is closed
+    session_deadlines: Deadlines,
+    // senders to the shard tasks, empty if the server handles every packet itself
+    shards: Vec<Sender<ShardMessage>>,
+    shard_status: Vec<ShardStatus>,
+    // replaces the files the watch task polls, None if nothing is watched
+    watcher: Option<Sender<Vec<PathBuf>>>,
+    // ticks sent to the shards so far
+    ticks: u64,
+    clock: SharedClock
+}
+
+impl Server {
+
+    //
+    // static fn
+    //
+
+    pub fn new(config: ServerConfig) -> Server {
+        Server::with_clock(config, Arc::new(SystemClock))
+    }
+
+    // A server that reads the time from `clock` instead of the system
+    pub fn with_clock(config: ServerConfig, clock: SharedClock) -> Server {
+        let now = clock.now();
+
+        Server { 
+            maintenance: config.maintenance,
+            config, 
+            config_args: Vec::new(),
+            clients: HashMap::new(),
+            sessions: Sessions::new(),
+            resume_tokens: HashMap::new(),
+            valid_client_hashes: Vec::new(), 
+            build_secrets: BuildSecrets::new(),
+            ticket_signer: Arc::new(TicketSigner::generate()),
+            access_list: AccessList::new(),
+            shutdown: None,
+            saved_snapshot: SessionSnapshot::default(),
+            last_snapshot_time: now,
+            last_ping_pong: now,
+            silence_deadlines: Deadlines::new(),
+            session_deadlines: Deadlines::new(),
+            shards: Vec::new(),
+            shard_status: Vec::new(),
+            watcher: None,
+            ticks: 0,
+            clock
+        }
+    }
+
+    fn generate_key(length: usize) -> String {
+        rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(length)
+            .collect()
+    }
+
+    pub async fn poll(server: &mut Server) -> Result<(), Box<dyn std::error::Error>> {
+        let ipaddr = server.config.bind_address.clone() + ":" + &server.config.port.to_string();
+        let socket = UdpSocket::bind(ipaddr).expect("Failed to bind host socket");
+
+        let(tx, rx) = channel::unbounded();
+        spawn_console_task(tx.clone());
+        create_signal_thread(tx.clone());
+        server.watcher = Some(spawn_watch_task(tx.clone(), server.watched_paths()));
+
+        if let Some(address) = server.config.metrics_address() {
+            spawn_metrics_task(address);
+        }
+
+        if let Some(address) = server.config.admin_address() {
+            spawn_admin_task(tx.clone(), address, server.config.admin_token.clone());
+        }
+
+        Server::serve(server, socket, tx, rx).await
+    }
+
+    // Runs the server on a bound socket until it shuts down. Only the shard
+    // tasks are started, everything else arrives through `tx`. Replies are
+    // queued and sent in batches once each event is handled.
+    pub async fn serve(
+        server: &mut Server,
+        socket: UdpSocket,
+        tx: Sender<ThreadMessage>,
+        rx: Receiver<ThreadMessage>
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let receiver = Async::new(socket.try_clone()?)?;
+
+        for index in 0..server.config.worker_threads {
+            let (shard_tx, shard_rx) = channel::unbounded();
+            spawn_shard_task(index, shard_rx, tx.clone(), socket.try_clone()?, server.ticket_signer.clone());
+            server.shards.push(shard_tx);
+        }
+
+        server.shard_status = vec![ShardStatus::default(); server.shards.len()];
+
+        drop(tx);
+
+        // with shards a separate task reads the socket and routes each packet
+        let receiver = if server.shards.is_empty() {
+            Some(receiver)
+        } else {
+            spawn_listening_task(server.shards.clone(), receiver, server.config.receive_buffer_size);
+            None
+        };
+
+        info!(addr:% = socket.local_addr()?, worker_threads = server.shards.len(); "Server started");
+
+        let sender = BatchSender::new(socket);
+
+        // restored hosts are asked to reconnect and attest, the silence check drops any that don't
+        let restored: Vec<SocketAddr> = server.clients.keys().copied().collect();
+
+        for socket_address in &restored {
+            server.adopt_link(socket_address);
+            server.send_to(&sender, socket_address, &ServerPacket::Ping);
+            server.send_challenge(&sender, socket_address);
+        }
+
+        let tick_duration = server.config.tick_duration();
+        let mut next_tick = server.now() + tick_duration;
+        let mut datagrams = Datagrams::new(server.config.receive_buffer_size);
+
+        loop {
+            let now = server.now();
+
+            if now >= next_tick {
+                // a tick that is already late is skipped rather than run back to back
+                let skipped = (now.duration_since(next_tick).as_nanos() / tick_duration.as_nanos()) as u32;
+
+                if skipped > 0 {
+                    for _ in 0..skipped {
+                        METRICS.tick_overrun();
+                    }
+
+                    warn!(skipped; "Server running behind, skipping ticks");
+                }
+
+                next_tick += tick_duration * (skipped + 1);
+
+                let stop = server.tick(&sender);
+                let _ = sender.flush();
+
+                if stop {
+                    break;
+                }
+
+                continue;
+            }
+
+            let wake = server.next_deadline().map_or(next_tick, |deadline| deadline.min(next_tick));
+            let event = server.next_event(&rx, receiver.as_ref(), &mut datagrams, wake).await;
+
+            let stop = match event {
+                Event::Message(message) => server.handle_message(&sender, message),
+                Event::Datagrams => {
+                    datagrams.iter().any(|(socket_address, data)| server.handle_datagram(&sender, socket_address, data))
+                },
+                // don't crash if there's an error...
+                Event::ReadFailed => false,
+                Event::Deadline => {
+                    server.run_deadlines(&sender);
+                    false
+                },
+                Event::Closed => true
+            };
+
+            // dropped datagrams are resent like lost ones
+            let _ = sender.flush();
+
+            if stop {
+                break;
+            }
+        }
+
+        // shards hold senders to each other for redirects, so their channels are closed rather than dropped
+        for shard in &server.shards {
+            shard.close();
+        }
+
+        Ok(())
+    }
+
+    // Waits for a message, datagrams if the server reads the socket itself,
+    // or `wake`, whichever comes first
+    async fn next_event(
+        &self,
+        rx: &Receiver<ThreadMessage>,
+        receiver: Option<&Async<UdpSocket>>,
+        datagrams: &mut Datagrams,
+        wake: Instant
+    ) -> Event {
+        let message = async {
+            match rx.recv().await {
+                Ok(message) => Event::Message(message),
+                Err(_) => Event::Closed
+            }
+        };
+
+        let timer = async {
+            async_std::task::sleep(wake.saturating_duration_since(self.now())).await;
+            Event::Deadline
+        };
+
+        match receiver {
+            Some(receiver) => {
+                let datagram = async {
+                    match datagrams.receive(receiver).await {
+                        Ok(_) => Event::Datagrams,
+                        Err(_) => Event::ReadFailed
+                    }
+                };
+
+                future::or(message, future::or(datagram, timer)).await
+            },
+            None => future::or(message, timer).await
+        }
+    }
+
+    // Parses a datagram read from the socket, returns true once the server should stop
+    pub fn handle_datagram(&mut self, socket: &dyn runtime::Transport, socket_address: SocketAddr, data: &[u8]) -> bool {
+        match parse_client_packet(data) {
+            Some((id, packet)) => {
+                METRICS.packet_received(packet.name());
+                self.handle_message(socket, ThreadMessage::ClientPacket { socket_address, id, packet })
+            },
+            None => {
+                METRICS.unknown_packet();
+                debug!(addr:% = socket_address, bytes:? = data; "Received unknown packet");
+                false
+            }
+        }
+    }
+
+    // Pings if due, resends, runs deadlines and saves snapshots. Returns true
+    // once the server should stop.
+    pub fn tick(&mut self, socket: &dyn runtime::Transport) -> bool {
+        let time = self.now();
+
+        // every client is pinged on the same tick
+        let ping_due = time.duration_since(self.last_ping_pong).as_secs_f32() >= self.config.max_ping_pong_rate;
+
+        if ping_due {
+            self.last_ping_pong = time;
+        }
+
+        for client in self.clients.values_mut() {
+            if let ClientLink::Local(link) = &mut client.link {
+                link.tick(socket, ping_due);
+            }
+        }
+
+        // shards ping and resend for their own clients and report the silent ones
+        self.ticks += 1;
+
+        for shard in &self.shards {
+            let _ = shard.try_send(ShardMessage::Tick {
+                number: self.ticks,
+                ping: ping_due,
+                now: time,
+                max_silence: self.max_silence()
+            });
+        }
+
+        self.run_deadlines(socket);
+        self.access_list.remove_expired(time);
+        self.record_metrics();
+
+        if self.snapshot_due() {
+            self.save_sessions();
+        }
+
+        self.update_shutdown(socket)
+    }
+
+    // Handles one message from the other tasks, returns true once the server should stop
+    pub fn handle_message(&mut self, socket: &dyn runtime::Transport, message: ThreadMessage) -> bool {
+        match message {
+            ThreadMessage::ClientPacket {
+                socket_address,
+                id,
+                packet
+            } => {
+                // with shards, only the first packet from an address they don't know arrives here
+                if let Some(client) = self.clients.get_mut(&socket_address) {
+                    // a shard that is still to get the link holds the packet, it is answered below
+                    if let ClientLink::Local(link) = &mut client.link {
+                        if let Some(data) = link.receive(socket, id, packet) {
+                            self.handle_packet(socket, socket_address, id, data)
+                        }
+                    }
+                } else if self.resume_client(socket, socket_address, id, &packet) {
+                    // known client at a new address
+                } else if self.access_list.permits(&socket_address.ip(), self.now()) && !self.is_closing() {
+                    // new connection
+                    let mut client = Client::new(socket_address, self.config.tick_duration(), &self.clock);
+
+                    let data = match &mut client.link {
+                        ClientLink::Local(link) => link.receive(socket, id, packet),
+                        ClientLink::Shard(_) => None
+                    };
+
+                    if let Some(data) = data {
+                        self.resume_tokens.insert(client.resume_token, socket_address);
+                        self.clients.insert(socket_address, client);
+
+                        debug!(addr:% = socket_address, id; "New client");
+                        self.send_challenge(socket, &socket_address);
+                        self.send_resume_token(socket, &socket_address);
+                        self.send_motd(socket, &socket_address);
+                        self.handle_packet(socket, socket_address, id, data);
+
+                        // the first packet is answered here, the rest go to the client's shard
+                        self.adopt_link(&socket_address);
+                        self.schedule_silence_check(&socket_address);
+                    }
+                }
+
+                self.answer_shard(&socket_address);
+            }
+            ThreadMessage::OrderedPacket {
+                socket_address,
+                id,
+                packet
+            } => {
+                if self.has_client(&socket_address) {
+                    self.handle_packet(socket, socket_address, id, packet)
+                }
+            }
+            ThreadMessage::FileChanged(path) => {
+                if path == self.access_list_path() {
+                    self.reload_access_list(socket);
+                } else {
+                    // hashes, secrets or the config file
+                    self.reload();
+                }
+            }
+            ThreadMessage::Shutdown => {
+                if self.begin_shutdown(socket) {
+                    return true;
+                }
+            }
+            ThreadMessage::Reload => {
+                self.reload();
+                self.reload_access_list(socket);
+            }
+            ThreadMessage::ShardTicked { shard, tick, unacknowledged, silent } => {
+                self.shard_status[shard] = ShardStatus { tick, unacknowledged };
+
+                for socket_address in silent {
+                    // the report may be older than a kick or a resume
+                    if matches!(self.clients.get(&socket_address).map(|client| &client.link), Some(ClientLink::Shard(_))) {
+                        self.kick_silent_client(socket, &socket_address);
+                    }
+                }
+            }
+            ThreadMessage::Command(line) => {
+                match self.handle_command(socket, &line) {
+                    Ok(output) => info!("{}", output),
+                    Err(e) => warn!(command = line; "{}", e)
+                }
+            }
+            ThreadMessage::Admin { command, reply } => {
+                let output = self.handle_command(socket, &command);
+
+                match &output {
+                    Ok(_) => info!(command; "Admin command"),
+                    Err(e) => warn!(command, error:% = e; "Admin command failed")
+                }
+
+                // the admin task may have given up waiting
+                let _ = reply.try_send(output);
+            }
+        }
+
+        false
+    }
+
+    // Runs a console or admin command, returning its output
+    fn handle_command(&mut self, socket: &dyn runtime::Transport, line: &str) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        match words.as_slice() {
+            ["ban", range] | ["ban", range, _] => {
+                let duration = match words.get(2).map(|seconds| seconds.parse::<u64>()) {
+                    Some(Ok(seconds)) => Some(Duration::from_secs(seconds)),
+                    Some(Err(_)) => return Err(String::from("Ban duration must be a number of seconds")),
+                    None => None
+                };
+
+                let range = range.parse::<IpRange>().map_err(|e| format!("Cannot ban: {}", e))?;
+
+                self.access_list.ban(range, duration, self.now());
+                self.kick_denied_clients(socket);
+
+                match duration {
+                    Some(duration) => Ok(format!("Banned {} for {}s", range, duration.as_secs())),
+                    None => Ok(format!("Banned {}", range))
+                }
+            },
+            ["unban", range] => {
+                let range = range.parse::<IpRange>().map_err(|e| format!("Cannot unban: {}", e))?;
+
+                if self.access_list.unban(&range) {
+                    Ok(format!("Unbanned {}", range))
+                } else {
+                    Err(format!("{} is not banned", range))
+                }
+            },
+            ["allow", range] => {
+                let range = range.parse::<IpRange>().map_err(|e| format!("Cannot allow: {}", e))?;
+
+                self.access_list.allow(range);
+                self.kick_denied_clients(socket);
+
+                Ok(format!("Allowed {}", range))
+            },
+            ["disallow", range] => {
+                let range = range.parse::<IpRange>().map_err(|e| format!("Cannot disallow: {}", e))?;
+
+                if self.access_list.disallow(&range) {
+                    self.kick_denied_clients(socket);
+                    Ok(format!("Removed {} from the allow list", range))
+                } else {
+                    Err(format!("{} is not on the allow list", range))
+                }
+            },
+            ["kick", address] => {
+                let address = address.parse::<SocketAddr>().map_err(|_| format!("`{}` is not an ip:port address", address))?;
+
+                if !self.has_client(&address) {
+                    return Err(format!("{} is not connected", address));
+                }
+
+                self.kick_client(socket, &address, "Kicked by the server");
+
+                Ok(format!("Kicked {}", address))
+            },
+            ["close", key] => {
+                let host_addr = self.sessions.get(key).ok_or_else(|| format!("No session with key {}", key))?.host;
+
+                // the host stays connected and may create another one
+                self.end_session(socket, &host_addr);
+
+                Ok(format!("Closed session {} hosted by {}", key, host_addr))
+            },
+            ["clients"] => Ok(self.list_clients()),
+            ["sessions"] => Ok(self.list_sessions()),
+            ["maintenance"] => Ok(format!("Maintenance mode is {}", if self.maintenance { "on" } else { "off" })),
+            ["maintenance", toggle @ ("on" | "off")] => {
+                self.maintenance = *toggle == "on";
+                Ok(format!("Maintenance mode is {}", toggle))
+            },
+            ["notice", ..] => {
+                let message = line.trim_start().trim_start_matches("notice").trim();
+
+                if message.is_empty() {
+                    return Err(String::from("Notice needs a message"));
+                }
+
+                let count = self.broadcast_notice(socket, message);
+
+                Ok(format!("Sent notice to {} clients", count))
+            },
+            ["reload"] => {
+                let reloaded = self.reload();
+                self.reload_access_list(socket);
+
+                if reloaded {
+                    Ok(String::from("Reloaded"))
+                } else {
+                    Err(String::from("Reload failed, see the server log"))
+                }
+            },
+            _ => Err(String::from(
+                "Unknown command. Commands: clients, sessions, kick <ip:port>, close <key>, \
+                ban <ip[/prefix]> [seconds], unban <ip[/prefix]>, allow <ip[/prefix]>, disallow <ip[/prefix]>, \
+                maintenance [on|off], notice <message>, reload"
+            ))
+        }
+    }
+
+    // One line per client, oldest first. Shards keep the packet state of
+    // their clients, so those show `-` for silent and unacknowledged.
+    fn list_clients(&self) -> String {
+        let mut clients: Vec<(&SocketAddr, &Client)> = self.clients.iter().collect();
+        clients.sort_by_key(|(_, client)| client.connected_at);
+
+        let now = self.now();
+        let mut lines = vec![format!("{} clients", clients.len())];
+
+        for (socket_address, client) in clients {
+            let (silent, unacknowledged) = match &client.link {
+                ClientLink::Local(link) => (
+                    format!("{}s", now.duration_since(link.last_message_time()).as_secs()),
+                    link.shipper.has_unacknowledged_packets().to_string()
+                ),
+                ClientLink::Shard(_) => (String::from("-"), String::from("-"))
+            };
+
+            lines.push(format!("{} age={}s silent={} build={} encrypted={} session={} unacknowledged={}",
+                socket_address,
+                now.duration_since(client.connected_at).as_secs(),
+                silent,
+                client.attested_build.as_deref().unwrap_or("-"),
+                client.encrypted,
+                self.sessions.hosted_by(socket_address).map(|session| session.key.as_str()).unwrap_or("-"),
+                unacknowledged
+            ));
+        }
+
+        lines.join("\n")
+    }
+
+    // One line per open session, oldest first
+    fn list_sessions(&self) -> String {
+        let mut sessions: Vec<&Session> = self.sessions.iter().collect();
+        sessions.sort_by_key(|session| session.created_at);
+
+        let now = self.now();
+        let mut lines = vec![format!("{} sessions", sessions.len())];
+
+        for session in sessions {
+            let expires_in = self.clients
+                .get(&session.host)
+                .and_then(|client| self.session_deadline(session, client))
+                .map(|deadline| format!("{}s", deadline.saturating_duration_since(now).as_secs()))
+                .unwrap_or_else(|| String::from("never"));
+
+            lines.push(format!("{} host={} age={}s expires_in={} password_protected={}",
+                session.key,
+                session.host,
+                now.duration_since(session.created_at).as_secs(),
+                expires_in,
+                session.password_protected
+            ));
+        }
+
+        lines.join("\n")
+    }
+
+    fn handle_packet(&mut self, socket: &dyn runtime::Transport, socket_address: SocketAddr, id: u32, packet: ClientPacket) {
+        if self.has_client(&socket_address) {
+            if !matches!(packet, ClientPacket::Pong | ClientPacket::Ack { .. }) {
+                let now = self.now();
+
+                if let Some(client) = self.clients.get_mut(&socket_address) {
+                    client.last_active = now;
+                }
+            }
+
+            match packet {
+                ClientPacket::Pong => {},
+                ClientPacket::Ack { id } => {
+                    // shards take the acks of their own clients
+                    if let Some(ClientLink::Local(link)) = self.clients.get_mut(&socket_address).map(|client| &mut client.link) {
+                        link.shipper.acknowledge(id);
+                    }
+                },
+                ClientPacket::KeyExchange { public_key } => {
+                    self.exchange_keys(socket, &socket_address, id, &public_key);
+                },
+                ClientPacket::Sealed { .. } => {
+                    // opened before reaching here
+                },
+                ClientPacket::Resume { .. } => {
+                    // a client that is already connected here has nothing to resume
+                    let reply = ServerPacket::Error{ id, code: ErrorCode::ResumeFailed, message: "Nothing to resume" };
+                    self.send_to(socket, &socket_address, &reply);
+                },
+                ClientPacket::Attest { build, mac } => {
+                    self.attest_client(socket, &socket_address, id, &build, &mac);
+                },
+                ClientPacket::Create { client_hash, password_protected } => {
+                    if !self.is_client_authorized(&socket_address, &client_hash) {
+                        info!(addr:% = socket_address, hash = client_hash; "Client is not attested and its hash is not accepted");
+                        let reply = ServerPacket::Error{ id, code: ErrorCode::NotAttested, message: "Client is not attested" };
+                        self.send_to(socket, &socket_address, &reply);
+                        return;
+                    }
+
+                    if self.shutdown.is_some() {
+                        let reply = ServerPacket::Error{ id, code: ErrorCode::ShuttingDown, message: "Server is shutting down" };
+                        self.send_to(socket, &socket_address, &reply);
+                        return;
+                    }
+
+                    if self.maintenance {
+                        let message = self.config.maintenance_message.clone();
+                        let reply = ServerPacket::Error{ id, code: ErrorCode::Maintenance, message: &message };
+                        self.send_to(socket, &socket_address, &reply);
+                        return;
+                    }
+
+                    if let Some(key) = self.create_session(&socket_address, password_protected) {
+                        let reply = ServerPacket::Create{ session_key: &key };
+                        self.send_to(socket, &socket_address, &reply);
+                    } else {
+                        let reply = ServerPacket::Error{ id, code: ErrorCode::SessionFailed, message: "Session failed to create" };
+                        self.send_to(socket, &socket_address, &reply);
+                    }
+                },
+                ClientPacket::Join { client_hash, session_key } => {
+                    if !self.is_client_authorized(&socket_address, &client_hash) {
+                        METRICS.join_failed(JoinFailure::NotAttested);
+                        let reply = ServerPacket::Error{ id, code: ErrorCode::NotAttested, message: "Client is not attested" };
+                        self.send_to(socket, &socket_address, &reply);
+                        return;
+                    }
+
+                    if self.maintenance {
+                        METRICS.join_failed(JoinFailure::Maintenance);
+                        let message = self.config.maintenance_message.clone();
+                        let reply = ServerPacket::Error{ id, code: ErrorCode::Maintenance, message: &message };
+                        self.send_to(socket, &socket_address, &reply);
+                        return;
+                    }
+
+                    let host_addr = if session_key.is_empty() {
+                        self.get_socket_addr_from_open_session(&socket_address)
+                    } else {
+                        self.get_socket_addr_from_session(&session_key, &socket_address)
+                    };
+
+                    if let Some(client_addr) = host_addr {
+                        self.match_clients(socket, client_addr, socket_address);
+                    } else {
+                        METRICS.join_failed(if session_key.is_empty() {
+                            JoinFailure::NoOpenSession
+                        } else {
+                            JoinFailure::SessionNotFound
+                        });
+
+                        self.send_to(socket, &socket_address, &ServerPacket::Join(None));
+                    }
+                },
+                ClientPacket::Close => {
+                    self.drop_client_session(&socket_address);
+                }
+            }
+        }
+    }
+
+    //
+    // non mut fn
+    //
+
+    fn has_key(&self, key: &str) -> bool {
+        self.sessions.contains_key(key)
+    }
+
+    fn has_client(&self, socket_address: &SocketAddr) -> bool {
+        self.clients.contains_key(socket_address)
+    }
+
+    fn valid_client_hash(&self, hash: &str) -> bool {
+        self.valid_client_hashes.iter().any(|h: &String| *h == *hash)
+    }
+
+    // Attested clients are always accepted, plaintext hashes only in legacy mode
+    fn is_client_authorized(&self, socket_address: &SocketAddr, client_hash: &str) -> bool {
+        let attested = self.clients
+            .get(socket_address)
+            .map(|client| client.attested_build.is_some())
+            .unwrap_or(false);
+
+        attested || (self.config.legacy_hashes && self.valid_client_hash(client_hash))
+    }
+
+    fn access_list_path(&self) -> PathBuf {
+        PathBuf::from(&self.config.access_list_path)
+    }
+
+    // Files that trigger a reload when they change
+    fn watched_paths(&self) -> Vec<PathBuf> {
+        let mut paths = vec![
+            self.access_list_path(),
+            PathBuf::from(&self.config.hashes_path),
+            PathBuf::from(&self.config.secrets_path)
+        ];
+
+        paths.extend(ServerConfig::file_path(&self.config_args).map(PathBuf::from));
+        paths
+    }
+
+    // Checks that clients, sessions and resume tokens agree with each other
+    pub fn check_invariants(&self) -> Result<(), String> {
+        self.sessions.check_indexes()?;
+
+        for session in self.sessions.iter() {
+            if !self.has_client(&session.host) {
+                return Err(format!("session {} belongs to {} which is not connected", session.key, session.host));
+            }
+        }
+
+        for (token, socket_address) in &self.resume_tokens {
+            match self.clients.get(socket_address) {
+                Some(client) if client.resume_token == *token => {},
+                _ => return Err(format!("resume token for {} does not match a client", socket_address))
+            }
+        }
+
+        for (socket_address, client) in &self.clients {
+            if self.resume_tokens.get(&client.resume_token) != Some(socket_address) {
+                return Err(format!("client {} has no resume token", socket_address));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get_socket_addr_from_session(&self, key: &str, exclude_socket: &SocketAddr) -> Option<SocketAddr> {
+        if let Some(session) = self.sessions.get(key) {
+            if *exclude_socket != session.host {
+                return Some(session.host)
+            }
+        }
+
+        None
+    }
+
+    // Joins without a key are given the oldest public session
+    fn get_socket_addr_from_open_session(&self, exclude_socket: &SocketAddr) -> Option<SocketAddr> {
+        self.sessions.oldest_open(exclude_socket)
+    }
+
+    //
+    // mut fn
+    //
+
+    pub fn support_client_hashes(&mut self, hashes: Vec<String>) {
+        self.valid_client_hashes = hashes;
+    }
+
+    pub fn support_build_secrets(&mut self, build_secrets: BuildSecrets) {
+        self.build_secrets = build_secrets;
+    }
+
+    // Reliably sends a packet to a connected client, does nothing if it's gone
+    fn send_to(&mut self, socket: &dyn runtime::Transport, socket_address: &SocketAddr, packet: &ServerPacket) {
+        if let Some(client) = self.clients.get_mut(socket_address) {
+            client.send(socket, &self.shards, socket_address, packet);
+        }
+    }
+
+    fn send_challenge(&mut self, socket: &dyn runtime::Transport, socket_address: &SocketAddr) {
+        if let Some(client) = self.clients.get_mut(socket_address) {
+            let nonce = generate_nonce();
+            client.challenge = Some(nonce);
+            client.send(socket, &self.shards, socket_address, &ServerPacket::Challenge { nonce: &nonce });
+        }
+    }
+
+    fn send_resume_token(&mut self, socket: &dyn runtime::Transport, socket_address: &SocketAddr) {
+        if let Some(client) = self.clients.get_mut(socket_address) {
+            let token = client.resume_token;
+            client.send(socket, &self.shards, socket_address, &ServerPacket::Resume { token: &token });
+        }
+    }
+
+    // Moves the client holding `token` to `new_address`, keeping its packet
+    // state and session, and gives it a fresh token. Returns false if the
+    // packet is not a resume with a known token.
+    fn resume_client(&mut self, socket: &dyn runtime::Transport, new_address: SocketAddr, id: u32, packet: &ClientPacket) -> bool {
+        let token = match packet {
+            ClientPacket::Resume { token } => token,
+            _ => return false
+        };
+
+        let old_address = match ResumeToken::try_from(token.as_slice()).ok().and_then(|token| self.resume_tokens.remove(&token)) {
+            Some(old_address) => old_address,
+            None => {
+                debug!(addr:% = new_address; "Resume with an unknown token");
+                return false;
+            }
+        };
+
+        let mut client = match self.clients.remove(&old_address) {
+            Some(client) => client,
+            None => return false
+        };
+
+        if !self.access_list.permits(&new_address.ip(), self.now()) {
+            // the old address keeps its connection and token
+            self.resume_tokens.insert(client.resume_token, old_address);
+            self.clients.insert(old_address, client);
+            return true;
+        }
+
+        match &mut client.link {
+            ClientLink::Local(link) => {
+                link.set_socket_address(new_address);
+
+                // acks the resume packet and keeps the id sequence going
+                link.reciever.sort_packets(socket, id, ClientPacket::Pong);
+            },
+            // the link stays with its shard, packets from the new address are redirected to it
+            ClientLink::Shard(index) => {
+                let _ = self.shards[*index].try_send(ShardMessage::Move { from: old_address, to: new_address, id });
+                self.forget_redirect(&old_address, *index);
+            }
+        }
+
+        self.sessions.move_host(&old_address, new_address);
+
+        client.resume_token = generate_resume_token();
+
+        self.resume_tokens.insert(client.resume_token, new_address);
+        self.clients.insert(new_address, client);
+
+        self.silence_deadlines.cancel(&old_address);
+        self.session_deadlines.cancel(&old_address);
+        self.schedule_silence_check(&new_address);
+        self.schedule_session_expiry(&new_address);
+        self.send_resume_token(socket, &new_address);
+
+        info!(addr:% = new_address, old:% = old_address; "Client resumed");
+
+        true
+    }
+
+    fn send_motd(&mut self, socket: &dyn runtime::Transport, socket_address: &SocketAddr) {
+        if self.config.motd.is_empty() {
+            return;
+        }
+
+        if let Some(client) = self.clients.get_mut(socket_address) {
+            client.send(socket, &self.shards, socket_address, &ServerPacket::Notice { message: &self.config.motd });
+        }
+    }
+
+    // Reliably sends a notice to every connected client
+    fn broadcast_notice(&mut self, socket: &dyn runtime::Transport, message: &str) -> usize {
+        for (socket_address, client) in self.clients.iter_mut() {
+            client.send(socket, &self.shards, socket_address, &ServerPacket::Notice { message });
+        }
+
+        self.clients.len()
+    }
+
+    fn attest_client(&mut self, socket: &dyn runtime::Transport, socket_address: &SocketAddr, id: u32, build: &str, mac: &[u8]) {
+        let client = match self.clients.get_mut(socket_address) {
+            Some(client) => client,
+            None => return
+        };
+
+        if client.attested_build.is_some() {
+            client.send(socket, &self.shards, socket_address, &ServerPacket::Attest { success: true });
+            return;
+        }
+
+        // each nonce can only be answered once
+        let success = match client.challenge.take() {
+            Some(nonce) => self.build_secrets.verify(build, &nonce, mac),
+            None => false
+        };
+
+        client.send(socket, &self.shards, socket_address, &ServerPacket::Attest { success });
+
+        if success {
+            info!(addr:% = socket_address, build; "Client attested");
+            client.attested_build = Some(build.to_string());
+        } else {
+            warn!(addr:% = socket_address, build; "Client failed attestation");
+            client.send(socket, &self.shards, socket_address, &ServerPacket::Error { id, code: ErrorCode::AttestationFailed, message: "Attestation failed" });
+            self.send_challenge(socket, socket_address);
+        }
+    }
+
+    fn exchange_keys(&mut self, socket: &dyn runtime::Transport, socket_address: &SocketAddr, id: u32, public_key: &[u8]) {
+        let client = match self.clients.get_mut(socket_address) {
+            Some(client) => client,
+            None => return
+        };
+
+        let switched = match &mut client.link {
+            ClientLink::Local(link) => link.exchange_keys(socket, id, public_key, &self.ticket_signer),
+            // shards answer key exchanges themselves and only pass on the ones that worked
+            ClientLink::Shard(_) => !client.encrypted
+        };
+
+        if switched {
+            client.encrypted = true;
+            info!(addr:% = socket_address; "Client switched to encrypted transport");
+        }
+    }
+
+    // Arguments the config is rebuilt from on reload
+    pub fn reload_config_with(&mut self, args: Vec<String>) {
+        self.config_args = args;
+    }
+
+    // Re-reads the config, client hashes and build secrets and swaps them in
+    // all at once, keeping clients and sessions. Nothing changes if any fail.
+    fn reload(&mut self) -> bool {
+        let mut config = match ServerConfig::load(&self.config_args) {
+            Ok(config) => config,
+            Err(errors) => {
+                for error in errors {
+                    error!(error:% = error; "Reload failed, keeping the running configuration");
+                }
+
+                return false;
+            }
+        };
+
+        let hashes = if config.legacy_hashes {
+            match file_read_lines(&config.hashes_path) {
+                Ok(hashes) => hashes,
+                Err(e) => {
+                    error!(path = config.hashes_path, error:% = e; "Reload failed, keeping the running configuration");
+                    return false;
+                }
+            }
+        } else {
+            Vec::new()
+        };
+
+        let build_secrets = match BuildSecrets::load(&config.secrets_path) {
+            Ok(build_secrets) => build_secrets,
+            Err(e) => {
+                error!(error:% = e; "Reload failed, keeping the running configuration");
+                return false;
+            }
+        };
+
+        for (key, old, new) in self.config.changes(&config) {
+            if RESTART_KEYS.contains(&key) {
+                warn!(setting = key, old = redact(key, &old), new = redact(key, &new); "Config changed but needs a restart to take effect");
+                config.set(key, &old).ok();
+            } else {
+                info!(setting = key, old = redact(key, &old), new = redact(key, &new); "Config changed");
+            }
+        }
+
+        let added = hashes.iter().filter(|hash| !self.valid_client_hashes.contains(hash)).count();
+        let removed = self.valid_client_hashes.iter().filter(|hash| !hashes.contains(hash)).count();
+
+        if added > 0 || removed > 0 {
+            info!(added, removed; "Client hashes reloaded");
+        }
+
+        if build_secrets.len() != self.build_secrets.len() {
+            info!(builds = build_secrets.len(), was = self.build_secrets.len(); "Build secrets reloaded");
+        }
+
+        if let Err(e) = logging::configure(&config.log_level, &config.log_format) {
+            error!(error:% = e; "Invalid log settings");
+        }
+
+        // a changed file setting overrides the admin toggle
+        if config.maintenance != self.config.maintenance {
+            self.maintenance = config.maintenance;
+        }
+
+        self.config = config;
+        self.valid_client_hashes = hashes;
+        self.build_secrets = build_secrets;
+
+        // the hashes and secrets may have moved
+        if let Some(watcher) = &self.watcher {
+            let _ = watcher.try_send(self.watched_paths());
+        }
+
+        // limits may have changed
+        let socket_addresses: Vec<SocketAddr> = self.clients.keys().cloned().collect();
+
+        for socket_address in socket_addresses {
+            self.schedule_silence_check(&socket_address);
+            self.schedule_session_expiry(&socket_address);
+        }
+
+        true
+    }
+
+    // When the client's session expires, None if it never does
+    fn session_deadline(&self, session: &Session, client: &Client) -> Option<Instant> {
+        let by_age = limit(self.config.max_session_age).map(|age| session.created_at + age);
+        let by_waiting = limit(self.config.max_waiting_time).map(|wait| client.last_active.max(session.created_at) + wait);
+
+        by_age.into_iter().chain(by_waiting).min()
+    }
+
+    // When the client counts as silent, going by the last packet it sent
+    fn max_silence(&self) -> Duration {
+        Duration::from_secs_f32(self.config.max_silence_duration.max(0.0))
+    }
+
+    // None for clients a shard has, it reports them once they go silent
+    fn silence_deadline(&self, socket_address: &SocketAddr) -> Option<Instant> {
+        match &self.clients.get(socket_address)?.link {
+            ClientLink::Local(link) => Some(link.last_message_time() + self.max_silence()),
+            ClientLink::Shard(_) => None
+        }
+    }
+
+    fn schedule_silence_check(&mut self, socket_address: &SocketAddr) {
+        if let Some(deadline) = self.silence_deadline(socket_address) {
+            self.silence_deadlines.schedule(*socket_address, deadline);
+        }
+    }
+
+    // When the session hosted at `socket_address` expires, None if it never does
+    fn session_expiry(&self, socket_address: &SocketAddr) -> Option<Instant> {
+        let session = self.sessions.hosted_by(socket_address)?;
+        let client = self.clients.get(socket_address)?;
+
+        self.session_deadline(session, client)
+    }
+
+    fn schedule_session_expiry(&mut self, socket_address: &SocketAddr) {
+        match self.session_expiry(socket_address) {
+            Some(deadline) => self.session_deadlines.schedule(*socket_address, deadline),
+            None => self.session_deadlines.cancel(socket_address)
+        }
+    }
+
+    fn next_deadline(&mut self) -> Option<Instant> {
+        self.silence_deadlines.next().into_iter().chain(self.session_deadlines.next()).min()
+    }
+
+    // Kicks silent clients and expires sessions whose deadline has passed.
+    // Packets and activity only push deadlines back, so a deadline that comes
+    // up is checked again and put back if it has moved.
+    fn run_deadlines(&mut self, socket: &dyn runtime::Transport) {
+        let now = self.now();
+
+        while let Some(socket_address) = self.silence_deadlines.pop_due(now) {
+            match self.silence_deadline(&socket_address) {
+                Some(deadline) if deadline > now => self.silence_deadlines.schedule(socket_address, deadline),
+                Some(_) => self.kick_silent_client(socket, &socket_address),
+                None => {}
+            }
+        }
+
+        while let Some(socket_address) = self.session_deadlines.pop_due(now) {
+            match self.session_expiry(&socket_address) {
+                Some(deadline) if deadline > now => self.session_deadlines.schedule(socket_address, deadline),
+                Some(_) => self.expire_session(socket, &socket_address),
+                None => {}
+            }
+        }
+    }
+
+    fn expire_session(&mut self, socket: &dyn runtime::Transport, socket_address: &SocketAddr) {
+        if let Some(key) = self.end_session(socket, socket_address) {
+            METRICS.session_expired();
+            info!(addr:% = socket_address, key; "Session expired");
+        }
+    }
+
+    // Closes the session and tells the host with `SessionExpired`, the host stays connected
+    fn end_session(&mut self, socket: &dyn runtime::Transport, socket_address: &SocketAddr) -> Option<String> {
+        let key = self.sessions.remove_hosted_by(socket_address)?.key;
+
+        self.send_to(socket, socket_address, &ServerPacket::SessionExpired { session_key: &key });
+
+        Some(key)
+    }
+
+    fn snapshot_due(&self) -> bool {
+        is_enabled(self.config.snapshot_interval)
+            && !self.config.sessions_path.is_empty()
+            && self.now().duration_since(self.last_snapshot_time).as_secs_f32() >= self.config.snapshot_interval
+    }
+
+    // Open sessions of hosts using the encrypted transport are left out,
+    // their keys are not kept so they could not be restored
+    fn snapshot_sessions(&self) -> SessionSnapshot {
+        let now = self.now();
+
+        let mut records: Vec<SessionRecord> = self.sessions
+            .iter()
+            .filter_map(|session| {
+                let client = self.clients.get(&session.host)?;
+
+                if client.encrypted {
+                    return None;
+                }
+
+                Some(SessionRecord::new(
+                    &session.key,
+                    session.host,
+                    session.password_protected,
+                    now.duration_since(session.created_at),
+                    &client.resume_token
+                ))
+            })
+            .collect();
+
+        records.sort_by(|a, b| a.key.cmp(&b.key));
+
+        SessionSnapshot::new(records)
+    }
+
+    // Writes the open sessions to `sessions_path` if they changed
+    fn save_sessions(&mut self) {
+        self.last_snapshot_time = self.now();
+
+        let snapshot = self.snapshot_sessions();
+
+        if snapshot == self.saved_snapshot {
+            return;
+        }
+
+        match snapshot.save(&self.config.sessions_path) {
+            Ok(_) => {
+                debug!(path = self.config.sessions_path, sessions = snapshot.sessions.len(); "Sessions saved");
+                self.saved_snapshot = snapshot;
+            },
+            Err(e) => error!(error:% = e; "Sessions could not be saved")
+        }
+    }
+
+    // Recreates the hosts of sessions saved by the last run
+    pub fn restore_sessions(&mut self) {
+        if !is_enabled(self.config.snapshot_interval) || self.config.sessions_path.is_empty() {
+            return;
+        }
+
+        let snapshot = match SessionSnapshot::load(&self.config.sessions_path) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                warn!(error:% = e; "Sessions were not restored");
+                return;
+            }
+        };
+
+        let now = self.now();
+
+        for record in &snapshot.sessions {
+            if self.has_key(&record.key) || self.has_client(&record.host) || !self.access_list.permits(&record.host.ip(), now) {
+                continue;
+            }
+
+            // attestations are not saved, the host is challenged again on start
+            let mut client = Client::new(record.host, self.config.tick_duration(), &self.clock);
+
+            if let Some(token) = record.resume_token() {
+                client.resume_token = token;
+            }
+
+            self.resume_tokens.insert(client.resume_token, record.host);
+            self.clients.insert(record.host, client);
+            self.sessions.insert(&record.key, record.host, record.password_protected, record.created_at(now));
+
+            self.schedule_silence_check(&record.host);
+            self.schedule_session_expiry(&record.host);
+        }
+
+        info!(path = self.config.sessions_path, sessions = self.sessions.len(); "Sessions restored");
+        self.saved_snapshot = snapshot;
+    }
+
+    pub fn load_access_list(&mut self) {
+        if let Err(e) = self.access_list.load(&self.config.access_list_path, self.now()) {
+            warn!(error:% = e; "Access list was not loaded");
+        }
+    }
+
+    fn reload_access_list(&mut self, socket: &dyn runtime::Transport) {
+        let path = self.config.access_list_path.clone();
+
+        match self.access_list.load(&path, self.now()) {
+            Ok(_) => {
+                info!(path; "Access list reloaded");
+                self.kick_denied_clients(socket);
+            },
+            Err(e) => error!(error:% = e; "Access list was not reloaded")
+        }
+    }
+
+    // Kick connected clients that the access list no longer permits
+    fn kick_denied_clients(&mut self, socket: &dyn runtime::Transport) {
+        let kick_list: Vec<SocketAddr> = self.clients
+            .keys()
+            .filter(|socket_address| !self.access_list.permits(&socket_address.ip(), self.now()))
+            .cloned()
+            .collect();
+
+        for socket_address in kick_list {
+            info!(addr:% = socket_address; "Dropping host due to access list");
+            self.kick_client(socket, &socket_address, "Address is not permitted");
+        }
+    }
+
+    // Sends a single close packet, the client is forgotten straight away
+    fn kick_client(&mut self, socket: &dyn runtime::Transport, socket_address: &SocketAddr, reason: &str) {
+        self.send_to(socket, socket_address, &ServerPacket::Close { reason });
+        self.drop_client(socket_address);
+    }
+
+    fn kick_silent_client(&mut self, socket: &dyn runtime::Transport, socket_address: &SocketAddr) {
+        info!(addr:% = socket_address; "Dropping host due to silence");
+        self.kick_client(socket, socket_address, "Dropped due to silence");
+    }
+
+    // Hands the client's link to the shard task for its address, which
+    // handles its packets from then on
+    fn adopt_link(&mut self, socket_address: &SocketAddr) {
+        if self.shards.is_empty() {
+            return;
+        }
+
+        let index = shard_for(socket_address, self.shards.len());
+
+        if let Some(client) = self.clients.get_mut(socket_address) {
+            if let ClientLink::Local(link) = std::mem::replace(&mut client.link, ClientLink::Shard(index)) {
+                let _ = self.shards[index].try_send(ShardMessage::Adopt { socket_address: *socket_address, link });
+            }
+        }
+    }
+
+    fn forget_link(&self, socket_address: &SocketAddr, client: &Client) {
+        if let ClientLink::Shard(index) = client.link {
+            let _ = self.shards[index].try_send(ShardMessage::Forget(*socket_address));
+            self.forget_redirect(socket_address, index);
+        }
+    }
+
+    // Clears the redirect to `owner` kept by the shard an address is read by, if that is another shard
+    fn forget_redirect(&self, socket_address: &SocketAddr, owner: usize) {
+        let index = shard_for(socket_address, self.shards.len());
+
+        if index != owner {
+            let _ = self.shards[index].try_send(ShardMessage::Forget(*socket_address));
+        }
+    }
+
+    // Tells the shard that passed on the first packet from an address what
+    // to do with the packets it holds for it. Adopted and moved links already
+    // went to that shard, resumed clients kept by another shard are
+    // redirected to it and anything else is forgotten.
+    fn answer_shard(&self, socket_address: &SocketAddr) {
+        if self.shards.is_empty() {
+            return;
+        }
+
+        let index = shard_for(socket_address, self.shards.len());
+
+        let message = match self.clients.get(socket_address).map(|client| &client.link) {
+            Some(ClientLink::Shard(owner)) if *owner == index => return,
+            Some(ClientLink::Shard(owner)) => ShardMessage::Redirect {
+                socket_address: *socket_address,
+                to: self.shards[*owner].clone()
+            },
+            Some(ClientLink::Local(_)) | None => ShardMessage::Forget(*socket_address)
+        };
+
+        let _ = self.shards[index].try_send(message);
+    }
+
+    fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    fn is_closing(&self) -> bool {
+        matches!(self.shutdown, Some(Shutdown::Closing { .. }))
+    }
+
+    // Returns true if the server should stop right away
+    fn begin_shutdown(&mut self, socket: &dyn runtime::Transport) -> bool {
+        match self.shutdown {
+            None if self.config.shutdown_drain_time > 0.0 && !self.sessions.is_empty() => {
+                info!(drain_time = self.config.shutdown_drain_time, sessions = self.sessions.len();
+                    "Shutting down, refusing new sessions while open ones are matched"
+                );
+
+                let drain_time = Duration::from_secs_f32(self.config.shutdown_drain_time);
+                self.shutdown = Some(Shutdown::Draining { until: self.now() + drain_time });
+            },
+            None | Some(Shutdown::Draining { .. }) => {
+                self.close_all_clients(socket);
+            },
+            Some(Shutdown::Closing { .. }) => {
+                warn!("Shutting down without waiting for clients");
+                return true;
+            }
+        }
+
+        false
+    }
+
+    // Advances a shutdown in progress, returns true once the server can stop
+    fn update_shutdown(&mut self, socket: &dyn runtime::Transport) -> bool {
+        match self.shutdown {
+            Some(Shutdown::Draining { until }) => {
+                if self.sessions.is_empty() || self.now() >= until {
+                    self.close_all_clients(socket);
+                }
+
+                false
+            },
+            Some(Shutdown::Closing { until, after_tick }) => {
+                let acknowledged = self.clients.values().all(|client| match &client.link {
+                    ClientLink::Local(link) => !link.shipper.has_unacknowledged_packets(),
+                    ClientLink::Shard(_) => true
+                });
+
+                // a shard's report counts once it was made after the closes were sent
+                let shards_acknowledged = self.shard_status
+                    .iter()
+                    .all(|status| status.tick > after_tick && status.unacknowledged == 0);
+
+                (acknowledged && shards_acknowledged) || self.now() >= until
+            },
+            None => false
+        }
+    }
+
+    fn close_all_clients(&mut self, socket: &dyn runtime::Transport) {
+        info!(clients = self.clients.len(); "Shutting down, closing clients");
+
+        for (socket_address, client) in self.clients.iter_mut() {
+            client.send(socket, &self.shards, socket_address, &ServerPacket::Close { reason: "Server shutting down" });
+        }
+
+        let ack_timeout = Duration::from_secs_f32(self.config.shutdown_ack_timeout);
+        self.shutdown = Some(Shutdown::Closing { until: self.now() + ack_timeout, after_tick: self.ticks });
+    }
+
+    fn create_session(&mut self, socket_address: &SocketAddr, password_protected: bool) -> Option<String> {
+        let mut result = None;
+
+        if !self.has_client(socket_address) {
+            return None;
+        }
+
+        if !self.sessions.has_host(socket_address) {
+            loop {
+                let new_key = Server::generate_key(self.config.key_length);
+
+                if self.sessions.insert(&new_key, *socket_address, password_protected, self.now()) {
+                    info!(addr:% = socket_address, key = new_key, password_protected; "Session created");
+                    self.schedule_session_expiry(socket_address);
+
+                    result = Some(new_key);
+                    break;
+                }
+            }
+        } else {
+            warn!(addr:% = socket_address; "Session cannot be created because it already exists");
+        }
+
+        result
+    }
+
+    // Pair a joining client with a session host, giving both the other's
+    // address, the same signed ticket and a fresh match key, then close their sessions
+    fn match_clients(&mut self, socket: &dyn runtime::Transport, host_addr: SocketAddr, joiner_addr: SocketAddr) {
+        let session_key = self.sessions
+            .hosted_by(&host_addr)
+            .map(|session| session.key.clone())
+            .unwrap_or_default();
+
+        let ticket = self.ticket_signer.sign(&MatchTicket::new(host_addr, joiner_addr, &session_key));
+        let match_key = generate_match_key();
+
+        // send to requester
+        self.send_to(socket, &joiner_addr, &ServerPacket::Join(Some(JoinReply {
+            client_addr: host_addr,
+            ticket: &ticket,
+            match_key: &match_key
+        })));
+
+        // send to session host
+        self.send_to(socket, &host_addr, &ServerPacket::Join(Some(JoinReply {
+            client_addr: joiner_addr,
+            ticket: &ticket,
+            match_key: &match_key
+        })));
+
+        // Drop any sessions related to these two clients
+        self.drop_client_session(&host_addr);
+        self.drop_client_session(&joiner_addr);
+
+        METRICS.match_made();
+    }
+
+    // Gauges are sampled once per tick
+    fn record_metrics(&self) {
+        METRICS.set_clients(self.clients.len());
+        METRICS.set_sessions(self.sessions.public_count(), self.sessions.private_count());
+    }
+
+    pub fn use_ticket_signer(&mut self, ticket_signer: TicketSigner) {
+        self.ticket_signer = Arc::new(ticket_signer);
+    }
+
+    // Drop the client session only (when a match is made)
+    fn drop_client_session(&mut self, socket_address: &SocketAddr) -> bool {
+        if self.has_client(socket_address) {
+            self.sessions.remove_hosted_by(socket_address);
+            return true;
+        }
+
+        false
+    }
+
+    // Drop the client entirely including associated resources
+    fn drop_client(&mut self, socket_address: &SocketAddr) -> bool {
+        if let Some(client) = self.clients.remove(socket_address) {
+            self.forget_link(socket_address, &client);
+            self.silence_deadlines.cancel(socket_address);
+            self.session_deadlines.cancel(socket_address);
+            self.resume_tokens.remove(&client.resume_token);
+            self.sessions.remove_hosted_by(socket_address);
+
+            return true;
+        }
+
+        false
+    }
+}
+
+//
+// util fn
+//
+
+// Periods of 0 turn a feature off
+fn is_enabled(seconds: f32) -> bool {
+    seconds > 0.0
+}
+
+// Limits of 0 seconds mean no limit
+fn limit(seconds: f32) -> Option<Duration> {
+    if is_enabled(seconds) { Some(Duration::from_secs_f32(seconds)) } else { None }
+}
+
+// Keeps secrets out of the log
+fn redact<'a>(key: &str, value: &'a str) -> &'a str {
+    if key == "admin_token" && !value.is_empty() { "<redacted>" } else { value }
+}
+
+pub fn file_read_lines(path: &str) -> std::io::Result<Vec<String>> {
+    let file = File::open(path)?;
+    let reader = BufReader::new(file);
+    let mut result = Vec::new();
+
+    // Read the file line by line using the lines() iterator from std::io::BufRead.
+    for line in reader.lines() {
+        result.push(line?);
+    }
+
+    Ok(result)
+}
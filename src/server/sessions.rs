@@ -0,0 +1,157 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::time::Instant;
+
+pub struct Session {
+    pub key: String,
+    pub host: SocketAddr,
+    pub password_protected: bool,
+    pub created_at: Instant,
+    // position in the open queue, ties on `created_at` keep insertion order
+    order: u64
+}
+
+// Open sessions, indexed by key, by host and by visibility. Every index is
+// updated together, so a lookup never has to scan.
+#[derive(Default)]
+pub struct Sessions {
+    by_host: HashMap<SocketAddr, Session>,
+    by_key: HashMap<String, SocketAddr>,
+    // public sessions, oldest first, for joins without a key
+    open: BTreeMap<(Instant, u64), SocketAddr>,
+    private_count: usize,
+    next_order: u64
+}
+
+impl Sessions {
+    pub fn new() -> Sessions {
+        Sessions::default()
+    }
+
+    // Fails if the key is taken or the host already has a session
+    pub fn insert(&mut self, key: &str, host: SocketAddr, password_protected: bool, created_at: Instant) -> bool {
+        if self.by_key.contains_key(key) || self.by_host.contains_key(&host) {
+            return false;
+        }
+
+        let order = self.next_order;
+        self.next_order += 1;
+
+        if password_protected {
+            self.private_count += 1;
+        } else {
+            self.open.insert((created_at, order), host);
+        }
+
+        self.by_key.insert(key.to_string(), host);
+        self.by_host.insert(host, Session { key: key.to_string(), host, password_protected, created_at, order });
+
+        true
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Session> {
+        self.by_host.get(self.by_key.get(key)?)
+    }
+
+    pub fn hosted_by(&self, host: &SocketAddr) -> Option<&Session> {
+        self.by_host.get(host)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.by_key.contains_key(key)
+    }
+
+    pub fn has_host(&self, host: &SocketAddr) -> bool {
+        self.by_host.contains_key(host)
+    }
+
+    // The oldest public session not hosted by `exclude`
+    pub fn oldest_open(&self, exclude: &SocketAddr) -> Option<SocketAddr> {
+        self.open.values().find(|host| *host != exclude).cloned()
+    }
+
+    pub fn remove_hosted_by(&mut self, host: &SocketAddr) -> Option<Session> {
+        let session = self.by_host.remove(host)?;
+        self.by_key.remove(&session.key);
+
+        if session.password_protected {
+            self.private_count -= 1;
+        } else {
+            self.open.remove(&(session.created_at, session.order));
+        }
+
+        Some(session)
+    }
+
+    // Moves a session to its host's new address, keeping its place in the queue
+    pub fn move_host(&mut self, from: &SocketAddr, to: SocketAddr) -> bool {
+        if self.by_host.contains_key(&to) {
+            return false;
+        }
+
+        let mut session = match self.by_host.remove(from) {
+            Some(session) => session,
+            None => return false
+        };
+
+        session.host = to;
+        self.by_key.insert(session.key.clone(), to);
+
+        if !session.password_protected {
+            self.open.insert((session.created_at, session.order), to);
+        }
+
+        self.by_host.insert(to, session);
+
+        true
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Session> {
+        self.by_host.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_host.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_host.is_empty()
+    }
+
+    pub fn public_count(&self) -> usize {
+        self.open.len()
+    }
+
+    pub fn private_count(&self) -> usize {
+        self.private_count
+    }
+
+    // Checks that every index holds the same sessions
+    pub fn check_indexes(&self) -> Result<(), String> {
+        for (host, session) in &self.by_host {
+            if session.host != *host {
+                return Err(format!("session {} is stored under {} but hosted by {}", session.key, host, session.host));
+            }
+
+            if self.by_key.get(&session.key) != Some(host) {
+                return Err(format!("session {} hosted by {} is missing from the key index", session.key, host));
+            }
+
+            let queued = self.open.get(&(session.created_at, session.order)) == Some(host);
+
+            if queued == session.password_protected {
+                return Err(format!("session {} hosted by {} is {} the open queue", session.key, host, if queued { "in" } else { "missing from" }));
+            }
+        }
+
+        if self.by_key.len() != self.by_host.len() {
+            return Err(format!("{} keys for {} sessions", self.by_key.len(), self.by_host.len()));
+        }
+
+        if self.open.len() + self.private_count != self.by_host.len() {
+            return Err(format!("{} public and {} private for {} sessions", self.open.len(), self.private_count, self.by_host.len()));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,6 @@
+mod network;
+pub use network::{Datagram, NetworkConditions, SimulatedNetwork};
+
+#[allow(clippy::module_inception)]
+mod simulation;
+pub use simulation::Simulation;
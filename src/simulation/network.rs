@@ -0,0 +1,130 @@
+use crate::runtime::{Transport, VirtualClock};
+use rand::prng::XorShiftRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+// How the simulated network mistreats datagrams, probabilities are 0.0 to 1.0
+#[derive(Clone, Debug, Default)]
+pub struct NetworkConditions {
+    pub loss: f64,
+    pub duplication: f64,
+    // reordered datagrams are held back up to twice the latency plus jitter
+    pub reordering: f64,
+    pub latency: Duration,
+    // random extra latency, up to this much
+    pub jitter: Duration
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Datagram {
+    // virtual time since the simulation started
+    pub deliver_at: Duration,
+    pub from: SocketAddr,
+    pub to: SocketAddr,
+    // orders datagrams on the same link that are due at the same time
+    sequence: u64,
+    pub data: Vec<u8>
+}
+
+// Each direction between two addresses draws from its own generator, so the
+// fate of a datagram doesn't depend on what else was sent in between
+struct Link {
+    rng: XorShiftRng,
+    sequence: u64
+}
+
+pub struct SimulatedNetwork {
+    seed: u64,
+    conditions: NetworkConditions,
+    clock: Arc<VirtualClock>,
+    // the address the server sends from
+    address: SocketAddr,
+    links: RefCell<HashMap<(SocketAddr, SocketAddr), Link>>,
+    in_flight: RefCell<BinaryHeap<Reverse<Datagram>>>
+}
+
+impl SimulatedNetwork {
+    pub fn new(seed: u64, conditions: NetworkConditions, clock: Arc<VirtualClock>, address: SocketAddr) -> SimulatedNetwork {
+        SimulatedNetwork {
+            seed,
+            conditions,
+            clock,
+            address,
+            links: RefCell::new(HashMap::new()),
+            in_flight: RefCell::new(BinaryHeap::new())
+        }
+    }
+
+    pub fn send(&self, from: SocketAddr, to: SocketAddr, data: &[u8]) {
+        let mut links = self.links.borrow_mut();
+        let seed = self.seed;
+
+        let link = links.entry((from, to)).or_insert_with(|| {
+            let mut hasher = DefaultHasher::new();
+            (from, to).hash(&mut hasher);
+
+            Link {
+                rng: XorShiftRng::seed_from_u64(seed ^ hasher.finish()),
+                sequence: 0
+            }
+        });
+
+        if link.rng.gen_bool(self.conditions.loss) {
+            return;
+        }
+
+        let copies = if link.rng.gen_bool(self.conditions.duplication) { 2 } else { 1 };
+
+        for _ in 0..copies {
+            let mut delay = self.conditions.latency + self.conditions.jitter.mul_f64(link.rng.gen::<f64>());
+
+            if link.rng.gen_bool(self.conditions.reordering) {
+                delay += (self.conditions.latency + self.conditions.jitter).mul_f64(2.0 * link.rng.gen::<f64>());
+            }
+
+            link.sequence += 1;
+
+            self.in_flight.borrow_mut().push(Reverse(Datagram {
+                deliver_at: self.clock.elapsed() + delay,
+                from,
+                to,
+                sequence: link.sequence,
+                data: data.to_vec()
+            }));
+        }
+    }
+
+    // Removes every datagram due by now, in delivery order
+    pub fn deliver_due(&self) -> Vec<Datagram> {
+        let now = self.clock.elapsed();
+        let mut in_flight = self.in_flight.borrow_mut();
+        let mut due = Vec::new();
+
+        while in_flight.peek().map(|Reverse(datagram)| datagram.deliver_at <= now).unwrap_or(false) {
+            if let Some(Reverse(datagram)) = in_flight.pop() {
+                due.push(datagram);
+            }
+        }
+
+        due
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.borrow().len()
+    }
+}
+
+// Datagrams sent by the server
+impl Transport for SimulatedNetwork {
+    fn send_to(&self, data: &[u8], address: SocketAddr) -> std::io::Result<usize> {
+        self.send(self.address, address, data);
+        Ok(data.len())
+    }
+}
@@ -0,0 +1,127 @@
+use crate::config::ServerConfig;
+use crate::runtime::VirtualClock;
+use crate::server::Server;
+use crate::simulation::{Datagram, NetworkConditions, SimulatedNetwork};
+use crate::threads::ThreadMessage;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Runs a server on a single thread against a virtual clock and a simulated
+// network. Nothing happens between steps, so a run only depends on the seed
+// and on what the clients send.
+pub struct Simulation {
+    server: Server,
+    clock: Arc<VirtualClock>,
+    network: SimulatedNetwork,
+    address: SocketAddr,
+    tick: Duration,
+    // datagrams delivered to clients, waiting to be read
+    inboxes: HashMap<SocketAddr, VecDeque<Vec<u8>>>,
+    // every datagram delivered so far
+    trace: Vec<Datagram>,
+    stopped: bool
+}
+
+impl Simulation {
+    pub fn new(config: ServerConfig, seed: u64, conditions: NetworkConditions) -> Simulation {
+        let clock = Arc::new(VirtualClock::new());
+        let address = SocketAddr::from(([10, 0, 0, 1], config.port));
+        let tick = config.tick_duration();
+
+        Simulation {
+            server: Server::with_clock(config, clock.clone()),
+            network: SimulatedNetwork::new(seed, conditions, clock.clone(), address),
+            clock,
+            address,
+            tick,
+            inboxes: HashMap::new(),
+            trace: Vec::new(),
+            stopped: false
+        }
+    }
+
+    pub fn server(&mut self) -> &mut Server {
+        &mut self.server
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    // Virtual time since the simulation started
+    pub fn elapsed(&self) -> Duration {
+        self.clock.elapsed()
+    }
+
+    pub fn trace(&self) -> &[Datagram] {
+        &self.trace
+    }
+
+    pub fn check_invariants(&self) -> Result<(), String> {
+        self.server.check_invariants()
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    // Sends a datagram from a client to the server
+    pub fn send(&self, from: SocketAddr, data: &[u8]) {
+        self.network.send(from, self.address, data);
+    }
+
+    // The next datagram delivered to a client
+    pub fn recv(&mut self, client: SocketAddr) -> Option<Vec<u8>> {
+        self.inboxes.get_mut(&client)?.pop_front()
+    }
+
+    // Advances the clock by one tick, delivers what arrived and runs the tick.
+    // Returns false once the server has stopped.
+    pub fn step(&mut self) -> bool {
+        if self.stopped {
+            return false;
+        }
+
+        self.clock.advance(self.tick);
+
+        for datagram in self.network.deliver_due() {
+            if datagram.to == self.address {
+                self.deliver_to_server(&datagram);
+            } else {
+                self.inboxes.entry(datagram.to).or_default().push_back(datagram.data.clone());
+            }
+
+            self.trace.push(datagram);
+        }
+
+        if !self.stopped && self.server.tick(&self.network) {
+            self.stopped = true;
+        }
+
+        !self.stopped
+    }
+
+    pub fn run_for(&mut self, duration: Duration) {
+        let until = self.clock.elapsed() + duration;
+
+        while self.clock.elapsed() < until && self.step() {}
+    }
+
+    pub fn shutdown(&mut self) {
+        self.handle(ThreadMessage::Shutdown);
+    }
+
+    fn deliver_to_server(&mut self, datagram: &Datagram) {
+        if !self.stopped && self.server.handle_datagram(&self.network, datagram.from, &datagram.data) {
+            self.stopped = true;
+        }
+    }
+
+    fn handle(&mut self, message: ThreadMessage) {
+        if !self.stopped && self.server.handle_message(&self.network, message) {
+            self.stopped = true;
+        }
+    }
+}
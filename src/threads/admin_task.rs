@@ -0,0 +1,140 @@
+use crate::threads::http::{read_request, write_response, Request};
+use crate::threads::ThreadMessage;
+use async_std::channel::{self, Sender};
+use async_std::net::{TcpListener, TcpStream};
+use async_std::prelude::*;
+use hmac::{Hmac, Mac};
+use log::{debug, error, info, warn};
+use rand::Rng;
+use sha2::Sha256;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+// How long to wait for the main loop to run a command
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Serves `POST /command` over plain HTTP. The body is a single console
+// command, which the main loop runs before replying.
+pub fn spawn_admin_task(tx: Sender<ThreadMessage>, address: SocketAddr, token: String) {
+    let listener = match std::net::TcpListener::bind(address) {
+        Ok(listener) => TcpListener::from(listener),
+        Err(e) => {
+            error!(addr:% = address, error:% = e; "Admin endpoint could not be started");
+            return;
+        }
+    };
+
+    if token.is_empty() && !address.ip().is_loopback() {
+        warn!(addr:% = address; "Admin endpoint is reachable from other hosts without a token");
+    }
+
+    info!(addr:% = address; "Accepting admin commands at /command");
+
+    async_std::task::spawn(async move {
+        let mut incoming = listener.incoming();
+
+        while let Some(stream) = incoming.next().await {
+            match stream {
+                Ok(stream) => {
+                    let tx = tx.clone();
+                    let token = token.clone();
+
+                    async_std::task::spawn(async move {
+                        if let Err(e) = respond(&tx, &token, stream).await {
+                            debug!(error:% = e; "Admin request failed");
+                        }
+                    });
+                },
+                Err(e) => debug!(error:% = e; "Admin connection failed")
+            }
+        }
+    });
+}
+
+async fn respond(tx: &Sender<ThreadMessage>, token: &str, stream: TcpStream) -> std::io::Result<()> {
+    let request = read_request(&stream).await?;
+
+    if request.method != "POST" || request.path != "/command" {
+        return write_response(&stream, "404 Not Found", "text/plain", "not found\n").await;
+    }
+
+    if !is_authorized(&request, token) {
+        return write_response(&stream, "401 Unauthorized", "text/plain", "missing or wrong bearer token\n").await;
+    }
+
+    let (reply_tx, reply_rx) = channel::bounded(1);
+
+    let message = ThreadMessage::Admin {
+        command: request.body.trim().to_string(),
+        reply: reply_tx
+    };
+
+    if tx.send(message).await.is_err() {
+        return write_response(&stream, "503 Service Unavailable", "text/plain", "server is stopping\n").await;
+    }
+
+    match async_std::future::timeout(REPLY_TIMEOUT, reply_rx.recv()).await {
+        Ok(Ok(Ok(reply))) => write_response(&stream, "200 OK", "text/plain", &(reply + "\n")).await,
+        Ok(Ok(Err(reply))) => write_response(&stream, "400 Bad Request", "text/plain", &(reply + "\n")).await,
+        _ => write_response(&stream, "503 Service Unavailable", "text/plain", "server did not reply\n").await
+    }
+}
+
+fn is_authorized(request: &Request, token: &str) -> bool {
+    if token.is_empty() {
+        return true;
+    }
+
+    request.header("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|given| tokens_match(given.trim(), token))
+        .unwrap_or(false)
+}
+
+// Compares MACs of both under a random key, in constant time, so how long
+// it takes says nothing about how much of the token was right or its length
+fn tokens_match(given: &str, token: &str) -> bool {
+    let mut key = [0u8; 32];
+    rand::thread_rng().fill(&mut key);
+
+    let mac = |value: &str| {
+        let mut hmac = Hmac::<Sha256>::new_from_slice(&key).expect("HMAC takes keys of any length");
+        hmac.update(value.as_bytes());
+        hmac
+    };
+
+    let expected = mac(token).finalize().into_bytes();
+
+    mac(given).verify_slice(&expected).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(authorization: Option<&str>) -> Request {
+        Request {
+            method: String::from("POST"),
+            path: String::from("/command"),
+            headers: authorization.map(|value| (String::from("authorization"), value.to_string())).into_iter().collect(),
+            body: String::new()
+        }
+    }
+
+    #[test]
+    fn only_the_exact_token_is_authorized() {
+        assert!(is_authorized(&request(Some("Bearer secret")), "secret"));
+        assert!(is_authorized(&request(Some("Bearer  secret ")), "secret"));
+
+        assert!(!is_authorized(&request(None), "secret"));
+        assert!(!is_authorized(&request(Some("secret")), "secret"));
+        assert!(!is_authorized(&request(Some("Bearer secre")), "secret"));
+        assert!(!is_authorized(&request(Some("Bearer secrets")), "secret"));
+        assert!(!is_authorized(&request(Some("Bearer ")), "secret"));
+    }
+
+    #[test]
+    fn no_token_authorizes_everyone() {
+        assert!(is_authorized(&request(None), ""));
+    }
+}
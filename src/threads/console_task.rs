@@ -0,0 +1,26 @@
+use crate::threads::ThreadMessage;
+use async_std::channel::Sender;
+use async_std::io::{self, BufReader};
+use async_std::prelude::*;
+
+// Forwards each line typed into the server's stdin as an admin command
+pub fn spawn_console_task(tx: Sender<ThreadMessage>) {
+    async_std::task::spawn(async move {
+        let mut lines = BufReader::new(io::stdin()).lines();
+
+        while let Some(line) = lines.next().await {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break
+            };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if tx.send(ThreadMessage::Command(line)).await.is_err() {
+                break;
+            }
+        }
+    });
+}
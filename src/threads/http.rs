@@ -0,0 +1,220 @@
+use async_std::io::{self, BufReader};
+use async_std::net::TcpStream;
+use async_std::prelude::*;
+use std::time::Duration;
+
+// Requests larger than this are rejected
+const MAX_BODY_SIZE: usize = 4096;
+
+// Longest request or header line, and most headers, before a request is rejected
+const MAX_LINE_LENGTH: usize = 8192;
+const MAX_HEADERS: usize = 64;
+
+// Slow clients are cut off after this
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Just enough HTTP/1.1 for the metrics and admin endpoints
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// Why a request could not be read. Requests that break the limits
+// are answered with their status before the connection is closed.
+enum ReadError {
+    Io(io::Error),
+    Rejected(&'static str, &'static str)
+}
+
+impl From<io::Error> for ReadError {
+    fn from(e: io::Error) -> ReadError {
+        ReadError::Io(e)
+    }
+}
+
+pub async fn read_request(stream: &TcpStream) -> io::Result<Request> {
+    let result = io::timeout(READ_TIMEOUT, async { Ok(read(stream).await) }).await?;
+
+    match result {
+        Ok(request) => Ok(request),
+        Err(ReadError::Io(e)) => Err(e),
+        Err(ReadError::Rejected(status, message)) => {
+            write_response(stream, status, "text/plain", &format!("{}\n", message)).await?;
+            Err(io::Error::new(io::ErrorKind::InvalidData, message))
+        }
+    }
+}
+
+async fn read(stream: &TcpStream) -> Result<Request, ReadError> {
+    let bad_request = |message| ReadError::Rejected("400 Bad Request", message);
+    let mut reader = BufReader::new(stream);
+
+    let request_line = read_line(&mut reader).await?
+        .ok_or_else(|| bad_request("request line too long"))?;
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().ok_or_else(|| bad_request("missing method"))?.to_string();
+    let path = parts.next().ok_or_else(|| bad_request("missing path"))?.to_string();
+
+    let mut headers = Vec::new();
+
+    loop {
+        let line = read_line(&mut reader).await?
+            .ok_or(ReadError::Rejected("431 Request Header Fields Too Large", "header too long"))?;
+
+        if line.trim().is_empty() {
+            break;
+        }
+
+        if headers.len() == MAX_HEADERS {
+            return Err(ReadError::Rejected("431 Request Header Fields Too Large", "too many headers"));
+        }
+
+        if let Some((key, value)) = line.split_once(':') {
+            headers.push((key.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    let mut request = Request { method, path, headers, body: String::new() };
+
+    let length = match request.header("Content-Length") {
+        Some(length) => length.parse::<usize>().map_err(|_| bad_request("bad content length"))?,
+        None => 0
+    };
+
+    if length > MAX_BODY_SIZE {
+        return Err(ReadError::Rejected("413 Payload Too Large", "body too large"));
+    }
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+    request.body = String::from_utf8(body).map_err(|_| bad_request("body is not utf-8"))?;
+
+    Ok(request)
+}
+
+// Reads up to `MAX_LINE_LENGTH` bytes of a line, None if it is longer.
+// An empty string means the stream ended.
+async fn read_line(reader: &mut BufReader<&TcpStream>) -> Result<Option<String>, ReadError> {
+    let mut line = String::new();
+    reader.take(MAX_LINE_LENGTH as u64 + 1).read_line(&mut line).await?;
+
+    if line.len() > MAX_LINE_LENGTH {
+        return Ok(None);
+    }
+
+    Ok(Some(line))
+}
+
+pub async fn write_response(mut stream: &TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+
+    stream.write_all(response.as_bytes()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::net::TcpListener;
+    use std::io::{Read, Write};
+
+    // Sends `data` to a fresh listener and returns what it read and replied
+    fn exchange(data: Vec<u8>) -> (io::Result<Request>, String) {
+        async_std::task::block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+
+            let client = std::thread::spawn(move || {
+                let mut stream = std::net::TcpStream::connect(address).unwrap();
+                stream.write_all(&data).unwrap();
+                stream.shutdown(std::net::Shutdown::Write).unwrap();
+
+                let mut reply = String::new();
+                let _ = stream.read_to_string(&mut reply);
+                reply
+            });
+
+            let (stream, _) = listener.accept().await.unwrap();
+            let request = read_request(&stream).await;
+
+            // unread bytes would make closing reset the connection before the reply is read
+            let _ = (&stream).read_to_end(&mut Vec::new()).await;
+            drop(stream);
+
+            (request, client.join().unwrap())
+        })
+    }
+
+    #[test]
+    fn requests_are_read_with_headers_and_body() {
+        let (request, _) = exchange(b"POST /command HTTP/1.1\r\nContent-Length: 8\r\nAuthorization: Bearer x\r\n\r\nsessions".to_vec());
+        let request = request.unwrap();
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/command");
+        assert_eq!(request.header("authorization"), Some("Bearer x"));
+        assert_eq!(request.body, "sessions");
+    }
+
+    #[test]
+    fn long_request_lines_are_rejected() {
+        let mut data = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE_LENGTH)).into_bytes();
+        data.truncate(MAX_LINE_LENGTH + 100);
+
+        let (request, reply) = exchange(data);
+
+        assert!(request.is_err());
+        assert!(reply.starts_with("HTTP/1.1 400 "), "{}", reply);
+    }
+
+    #[test]
+    fn long_headers_are_rejected() {
+        let data = format!("GET /metrics HTTP/1.1\r\nX-Long: {}\r\n\r\n", "a".repeat(MAX_LINE_LENGTH));
+        let (request, reply) = exchange(data.into_bytes());
+
+        assert!(request.is_err());
+        assert!(reply.starts_with("HTTP/1.1 431 "), "{}", reply);
+    }
+
+    #[test]
+    fn too_many_headers_are_rejected() {
+        let mut data = String::from("GET /metrics HTTP/1.1\r\n");
+
+        for index in 0..=MAX_HEADERS {
+            data += &format!("X-Header-{}: {}\r\n", index, index);
+        }
+
+        data += "\r\n";
+
+        let (request, reply) = exchange(data.into_bytes());
+
+        assert!(request.is_err());
+        assert!(reply.starts_with("HTTP/1.1 431 "), "{}", reply);
+    }
+
+    #[test]
+    fn large_bodies_are_rejected() {
+        let data = format!("POST /command HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_SIZE + 1);
+        let (request, reply) = exchange(data.into_bytes());
+
+        assert!(request.is_err());
+        assert!(reply.starts_with("HTTP/1.1 413 "), "{}", reply);
+    }
+}
@@ -0,0 +1,41 @@
+use crate::metrics::METRICS;
+use crate::packets::parse_client_packet;
+use crate::runtime::Datagrams;
+use crate::threads::{shard_for, ShardMessage};
+use async_io::Async;
+use async_std::channel::Sender;
+use log::debug;
+use std::net::UdpSocket;
+
+// With shards, one task reads the socket and hands each packet to the shard
+// for its address. Without them the server reads the socket itself.
+pub fn spawn_listening_task(shards: Vec<Sender<ShardMessage>>, socket: Async<UdpSocket>, buffer_size: usize) {
+    async_std::task::spawn(listen_loop(shards, socket, buffer_size));
+}
+
+async fn listen_loop(shards: Vec<Sender<ShardMessage>>, socket: Async<UdpSocket>, buffer_size: usize) {
+    let mut datagrams = Datagrams::new(buffer_size);
+
+    loop {
+        // don't crash if there's an error...
+        if datagrams.receive(&socket).await.is_err() {
+            continue;
+        }
+
+        for (src_addr, data) in datagrams.iter() {
+            if let Some((id, packet)) = parse_client_packet(data) {
+                METRICS.packet_received(packet.name());
+
+                let shard = &shards[shard_for(&src_addr, shards.len())];
+
+                // the shard stops with the server
+                if shard.send(ShardMessage::ClientPacket { socket_address: src_addr, id, packet }).await.is_err() {
+                    return;
+                }
+            } else {
+                METRICS.unknown_packet();
+                debug!(addr:% = src_addr, bytes:? = data; "Received unknown packet");
+            }
+        }
+    }
+}
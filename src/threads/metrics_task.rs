@@ -0,0 +1,45 @@
+use crate::metrics::METRICS;
+use crate::threads::http::{read_request, write_response};
+use async_std::net::{TcpListener, TcpStream};
+use async_std::prelude::*;
+use log::{debug, error, info};
+use std::net::SocketAddr;
+
+// Serves `GET /metrics` over plain HTTP
+pub fn spawn_metrics_task(address: SocketAddr) {
+    let listener = match std::net::TcpListener::bind(address) {
+        Ok(listener) => TcpListener::from(listener),
+        Err(e) => {
+            error!(addr:% = address, error:% = e; "Metrics endpoint could not be started");
+            return;
+        }
+    };
+
+    info!(addr:% = address; "Serving metrics at /metrics");
+
+    async_std::task::spawn(async move {
+        let mut incoming = listener.incoming();
+
+        while let Some(stream) = incoming.next().await {
+            match stream {
+                Ok(stream) => {
+                    async_std::task::spawn(async move {
+                        if let Err(e) = respond(stream).await {
+                            debug!(error:% = e; "Metrics request failed");
+                        }
+                    });
+                },
+                Err(e) => debug!(error:% = e; "Metrics connection failed")
+            }
+        }
+    });
+}
+
+async fn respond(stream: TcpStream) -> std::io::Result<()> {
+    let request = read_request(&stream).await?;
+
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/metrics") => write_response(&stream, "200 OK", "text/plain; version=0.0.4", &METRICS.render()).await,
+        _ => write_response(&stream, "404 Not Found", "text/plain", "not found\n").await
+    }
+}
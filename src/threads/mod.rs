@@ -1,8 +1,24 @@
 mod thread_message;
 pub use thread_message::ThreadMessage;
 
-pub mod clock_thread;
-pub use clock_thread::create_clock_thread;
+mod http;
 
-mod listening_thread;
-pub use listening_thread::create_listening_thread;
\ No newline at end of file
+mod listening_task;
+pub(crate) use listening_task::spawn_listening_task;
+
+mod shard_task;
+pub(crate) use shard_task::{spawn_shard_task, shard_for, ShardMessage};
+
+mod watch_task;
+pub use watch_task::spawn_watch_task;
+
+mod console_task;
+pub use console_task::spawn_console_task;
+
+mod signal_thread;
+pub use signal_thread::create_signal_thread;
+mod metrics_task;
+pub use metrics_task::spawn_metrics_task;
+
+mod admin_task;
+pub use admin_task::spawn_admin_task;
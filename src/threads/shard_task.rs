@@ -0,0 +1,217 @@
+use crate::packets::ClientPacket;
+use crate::runtime::BatchSender;
+use crate::server::Link;
+use crate::tickets::TicketSigner;
+use crate::threads::ThreadMessage;
+use async_std::channel::{Receiver, Sender};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub enum ShardMessage {
+    ClientPacket {
+        socket_address: SocketAddr,
+        id: u32,
+        packet: ClientPacket
+    },
+    // Ping if due, resend unacknowledged packets and report clients that
+    // sent nothing for `max_silence`
+    Tick {
+        number: u64,
+        ping: bool,
+        now: Instant,
+        max_silence: Duration
+    },
+    // The server accepted this client, its packets are handled here from now on
+    Adopt {
+        socket_address: SocketAddr,
+        link: Box<Link>
+    },
+    // The address is not a client here, or no longer is
+    Forget(SocketAddr),
+    // A client that resumed from this address is kept by the shard behind `to`
+    Redirect {
+        socket_address: SocketAddr,
+        to: Sender<ShardMessage>
+    },
+    // A packet the server built for one of this shard's clients
+    Send {
+        socket_address: SocketAddr,
+        name: &'static str,
+        payload: Vec<u8>
+    },
+    // The client resumed from `to`, `id` is its resume packet. Packets held
+    // for `to` are handled once it has moved.
+    Move {
+        from: SocketAddr,
+        to: SocketAddr,
+        id: u32
+    }
+}
+
+// Packets held for one address while the server decides what it is, more are dropped
+const MAX_PENDING: usize = 64;
+
+// Every packet from an address goes to the same shard, so its order is kept
+pub fn shard_for(socket_address: &SocketAddr, shards: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    socket_address.hash(&mut hasher);
+    (hasher.finish() % shards as u64) as usize
+}
+
+// Acks, orders and unseals the packets of the clients it was given, answers
+// acks, pongs and key exchanges itself and passes everything else on to the
+// server in order. The first packet from an address it doesn't know goes to
+// the server as it is, the rest are held until the server answers with
+// `Adopt`, `Move`, `Redirect` or `Forget`.
+pub fn spawn_shard_task(index: usize, rx: Receiver<ShardMessage>, tx: Sender<ThreadMessage>, socket: UdpSocket, ticket_signer: Arc<TicketSigner>) {
+    let shard = Shard {
+        index,
+        links: HashMap::new(),
+        pending: HashMap::new(),
+        redirects: HashMap::new(),
+        ticket_signer
+    };
+
+    async_std::task::spawn(shard_loop(shard, rx, tx, BatchSender::new(socket)));
+}
+
+struct Shard {
+    index: usize,
+    links: HashMap<SocketAddr, Box<Link>>,
+    // packets from addresses the server was asked about, the first one included
+    pending: HashMap<SocketAddr, Vec<(u32, ClientPacket)>>,
+    redirects: HashMap<SocketAddr, Sender<ShardMessage>>,
+    ticket_signer: Arc<TicketSigner>
+}
+
+async fn shard_loop(mut shard: Shard, rx: Receiver<ShardMessage>, tx: Sender<ThreadMessage>, socket: BatchSender) {
+    let mut forward = Vec::new();
+
+    while let Ok(message) = rx.recv().await {
+        let mut next = Some(message);
+
+        while let Some(message) = next {
+            shard.handle(&socket, message, &mut forward);
+
+            for message in forward.drain(..) {
+                // the server has stopped
+                if tx.send(message).await.is_err() {
+                    return;
+                }
+            }
+
+            // messages that are already waiting go out in the same batch
+            next = rx.try_recv().ok();
+        }
+
+        // dropped datagrams are resent like lost ones
+        let _ = socket.flush();
+    }
+}
+
+impl Shard {
+    // Adds what has to go on to the server to `forward`
+    fn handle(&mut self, socket: &BatchSender, message: ShardMessage, forward: &mut Vec<ThreadMessage>) {
+        match message {
+            ShardMessage::ClientPacket { socket_address, id, packet } => {
+                if self.links.contains_key(&socket_address) {
+                    self.receive(socket, socket_address, id, packet, forward);
+                } else if let Some(to) = self.redirects.get(&socket_address) {
+                    let _ = to.try_send(ShardMessage::ClientPacket { socket_address, id, packet });
+                } else if let Some(pending) = self.pending.get_mut(&socket_address) {
+                    // the client resends what is dropped
+                    if pending.len() < MAX_PENDING {
+                        pending.push((id, packet));
+                    }
+                } else {
+                    forward.push(ThreadMessage::ClientPacket { socket_address, id, packet: packet.clone() });
+                    self.pending.insert(socket_address, vec![(id, packet)]);
+                }
+            },
+            ShardMessage::Tick { number, ping, now, max_silence } => {
+                let mut unacknowledged = 0;
+                let mut silent = Vec::new();
+
+                for (socket_address, link) in self.links.iter_mut() {
+                    link.tick(socket, ping);
+
+                    if link.shipper.has_unacknowledged_packets() {
+                        unacknowledged += 1;
+                    }
+
+                    if now.saturating_duration_since(link.last_message_time()) >= max_silence {
+                        silent.push(*socket_address);
+                    }
+                }
+
+                forward.push(ThreadMessage::ShardTicked { shard: self.index, tick: number, unacknowledged, silent });
+            },
+            ShardMessage::Adopt { socket_address, link } => {
+                self.links.insert(socket_address, link);
+                self.replay(socket, socket_address, forward);
+            },
+            ShardMessage::Forget(socket_address) => {
+                self.links.remove(&socket_address);
+                self.pending.remove(&socket_address);
+                self.redirects.remove(&socket_address);
+            },
+            ShardMessage::Redirect { socket_address, to } => {
+                for (id, packet) in self.pending.remove(&socket_address).unwrap_or_default() {
+                    let _ = to.try_send(ShardMessage::ClientPacket { socket_address, id, packet });
+                }
+
+                self.redirects.insert(socket_address, to);
+            },
+            ShardMessage::Send { socket_address, name, payload } => {
+                if let Some(link) = self.links.get_mut(&socket_address) {
+                    link.shipper.send_built(socket, name, &payload);
+                }
+            },
+            ShardMessage::Move { from, to, id } => {
+                if let Some(mut link) = self.links.remove(&from) {
+                    link.set_socket_address(to);
+
+                    // acks the resume packet and keeps the id sequence going
+                    link.reciever.sort_packets(socket, id, ClientPacket::Pong);
+                    self.links.insert(to, link);
+                    self.replay(socket, to, forward);
+                }
+            }
+        }
+    }
+
+    // Handles the packets held for an address that now has a link. The ones
+    // the server already acked and handled, usually just the first, are skipped.
+    fn replay(&mut self, socket: &BatchSender, socket_address: SocketAddr, forward: &mut Vec<ThreadMessage>) {
+        for (id, packet) in self.pending.remove(&socket_address).unwrap_or_default() {
+            let passed = self.links.get(&socket_address).is_none_or(|link| link.reciever.has_passed(id));
+
+            if !passed {
+                self.receive(socket, socket_address, id, packet, forward);
+            }
+        }
+    }
+
+    fn receive(&mut self, socket: &BatchSender, socket_address: SocketAddr, id: u32, packet: ClientPacket, forward: &mut Vec<ThreadMessage>) {
+        let link = match self.links.get_mut(&socket_address) {
+            Some(link) => link,
+            None => return
+        };
+
+        match link.receive(socket, id, packet) {
+            Some(ClientPacket::Ack { id }) => link.shipper.acknowledge(id),
+            // the server only hears of exchanges that worked
+            Some(ClientPacket::KeyExchange { public_key }) => {
+                if link.exchange_keys(socket, id, &public_key, &self.ticket_signer) {
+                    forward.push(ThreadMessage::OrderedPacket { socket_address, id, packet: ClientPacket::KeyExchange { public_key } });
+                }
+            },
+            Some(ClientPacket::Pong) | None => {},
+            Some(packet) => forward.push(ThreadMessage::OrderedPacket { socket_address, id, packet })
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use crate::threads::ThreadMessage;
+use async_std::channel::Sender;
+
+// SIGHUP asks the server to reload its config, hashes, secrets and access list.
+// SIGINT and SIGTERM ask it to shut down gracefully. Waiting for signals
+// blocks, so this is the one real thread left.
+#[cfg(unix)]
+pub fn create_signal_thread(tx: Sender<ThreadMessage>) {
+    use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+    use signal_hook::iterator::Signals;
+
+    let mut signals = match Signals::new([SIGHUP, SIGINT, SIGTERM]) {
+        Ok(signals) => signals,
+        Err(e) => {
+            log::error!(error:% = e; "Signal handlers could not be installed");
+            return;
+        }
+    };
+
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            let message = match signal {
+                SIGHUP => ThreadMessage::Reload,
+                SIGINT | SIGTERM => ThreadMessage::Shutdown,
+                _ => continue
+            };
+
+            // never full, only fails once the server has stopped
+            if tx.try_send(message).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn create_signal_thread(_tx: Sender<ThreadMessage>) {}
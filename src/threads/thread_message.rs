@@ -1,10 +1,36 @@
 use crate::packets::{ClientPacket};
+use async_std::channel::Sender;
+use std::path::PathBuf;
 
 pub enum ThreadMessage {
-    Tick(Box<dyn FnOnce() + Send>),
     ClientPacket {
         socket_address: std::net::SocketAddr,
         id: u32,
         packet: ClientPacket
-    }
-}
\ No newline at end of file
+    },
+    // A packet a shard has already acked, unsealed and put in order
+    OrderedPacket {
+        socket_address: std::net::SocketAddr,
+        id: u32,
+        packet: ClientPacket
+    },
+    // Sent by a shard after each tick. `unacknowledged` counts its clients
+    // with packets they haven't acked, `silent` are the ones to kick.
+    ShardTicked {
+        shard: usize,
+        tick: u64,
+        unacknowledged: usize,
+        silent: Vec<std::net::SocketAddr>
+    },
+    FileChanged(PathBuf),
+    Command(String),
+    // A console command from the admin endpoint, answered with its
+    // output or an error
+    Admin {
+        command: String,
+        reply: Sender<Result<String, String>>
+    },
+    // Reload config, hashes, secrets and the access list
+    Reload,
+    Shutdown
+}
@@ -0,0 +1,103 @@
+use crate::threads::ThreadMessage;
+use async_std::channel::{self, Sender};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+pub const WATCH_RATE: f64 = 1.0;
+
+fn modified_time(path: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// Polls the files' modified times and notifies the server when one changes.
+// The returned sender replaces the set of files being watched.
+pub fn spawn_watch_task(tx: Sender<ThreadMessage>, paths: Vec<PathBuf>) -> Sender<Vec<PathBuf>> {
+    let target = std::time::Duration::from_secs_f64(1.0 / WATCH_RATE);
+    let (paths_tx, paths_rx) = channel::unbounded::<Vec<PathBuf>>();
+
+    let mut watched: Vec<(PathBuf, Option<SystemTime>)> = paths
+        .into_iter()
+        .map(|path| {
+            let modified = modified_time(&path);
+            (path, modified)
+        })
+        .collect();
+
+    async_std::task::spawn(async move {
+        loop {
+            async_std::task::sleep(target).await;
+
+            // files that stay watched keep their last modified time
+            while let Ok(paths) = paths_rx.try_recv() {
+                watched = paths
+                    .into_iter()
+                    .map(|path| {
+                        let modified = watched
+                            .iter()
+                            .find(|(watched_path, _)| *watched_path == path)
+                            .map_or_else(|| modified_time(&path), |(_, modified)| *modified);
+
+                        (path, modified)
+                    })
+                    .collect();
+            }
+
+            for (path, last_modified) in watched.iter_mut() {
+                let modified = modified_time(path);
+
+                if modified == *last_modified {
+                    continue;
+                }
+
+                *last_modified = modified;
+
+                if tx.send(ThreadMessage::FileChanged(path.clone())).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    paths_tx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn temp_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("matchmaker-watch-{}-{}.txt", name, std::process::id()));
+        std::fs::write(&path, "before").unwrap();
+        path
+    }
+
+    #[test]
+    fn only_the_latest_paths_are_watched() {
+        let old = temp_file("old");
+        let new = temp_file("new");
+        let (tx, rx) = channel::unbounded();
+
+        let watcher = spawn_watch_task(tx, vec![old.clone()]);
+        watcher.try_send(vec![new.clone()]).unwrap();
+
+        // the new set is taken on the next poll
+        std::thread::sleep(Duration::from_secs_f64(1.5 / WATCH_RATE));
+
+        std::fs::write(&old, "after").unwrap();
+        std::fs::write(&new, "after").unwrap();
+
+        let changed = async_std::task::block_on(async_std::future::timeout(Duration::from_secs(3), rx.recv()));
+
+        match changed {
+            Ok(Ok(ThreadMessage::FileChanged(path))) => assert_eq!(path, new),
+            _ => panic!("no change was reported")
+        }
+
+        let more = async_std::task::block_on(async_std::future::timeout(Duration::from_secs_f64(1.5 / WATCH_RATE), rx.recv()));
+        assert!(more.is_err(), "the replaced file is still watched");
+
+        std::fs::remove_file(old).unwrap();
+        std::fs::remove_file(new).unwrap();
+    }
+}
@@ -0,0 +1,337 @@
+use crate::encoding::{decode_hex, encode_hex};
+use crate::packets::{read_byte, read_string_u8, write_string_u8};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey, SIGNATURE_LENGTH};
+use log::warn;
+use rand::Rng;
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const TICKET_LIFETIME: Duration = Duration::from_secs(300);
+
+pub const MATCH_KEY_LEN: usize = 32;
+
+const TICKET_VERSION: u8 = 1;
+
+// Starts every signed key exchange, so no signature the server makes
+// for one can be passed off as a ticket or the other way around
+const KEY_EXCHANGE_CONTEXT: &[u8] = b"matchmaker key exchange";
+
+// A fresh symmetric key handed to both peers of a match so they can
+// set up an authenticated encrypted channel without another round trip
+pub fn generate_match_key() -> [u8; MATCH_KEY_LEN] {
+    let mut key = [0u8; MATCH_KEY_LEN];
+    rand::thread_rng().fill(&mut key);
+    key
+}
+
+// Issued to both peers of a match. Peers exchange tickets and verify them
+// with the server's public key, then check the sender's address against
+// the one named in the ticket, so a third party racing traffic to their
+// port cannot pose as the matched opponent.
+//
+// Layout (little endian):
+//   version u8, host str_u8, joiner str_u8, session_key str_u8,
+//   expires u64 (unix seconds), signature [u8; 64] over everything before it
+#[derive(Debug, PartialEq)]
+pub struct MatchTicket {
+    pub host: SocketAddr,
+    pub joiner: SocketAddr,
+    pub session_key: String,
+    pub expires: u64
+}
+
+impl MatchTicket {
+    pub fn new(host: SocketAddr, joiner: SocketAddr, session_key: &str) -> MatchTicket {
+        let expires = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            + TICKET_LIFETIME;
+
+        MatchTicket {
+            host,
+            joiner,
+            session_key: session_key.to_string(),
+            expires: expires.as_secs()
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![TICKET_VERSION];
+
+        write_string_u8(&mut buf, &self.host.to_string());
+        write_string_u8(&mut buf, &self.joiner.to_string());
+        write_string_u8(&mut buf, &self.session_key);
+        buf.extend(&self.expires.to_le_bytes());
+
+        buf
+    }
+
+    // Returns the ticket if the signature matches and it has not expired
+    pub fn verify(public_key: &[u8; 32], ticket: &[u8]) -> Option<MatchTicket> {
+        if ticket.len() < SIGNATURE_LENGTH {
+            return None;
+        }
+
+        let (body, signature) = ticket.split_at(ticket.len() - SIGNATURE_LENGTH);
+
+        let verifying_key = VerifyingKey::from_bytes(public_key).ok()?;
+        let signature = Signature::from_slice(signature).ok()?;
+
+        verifying_key.verify(body, &signature).ok()?;
+
+        let mut buf = body;
+
+        if read_byte(&mut buf)? != TICKET_VERSION {
+            return None;
+        }
+
+        let host = read_string_u8(&mut buf)?.parse().ok()?;
+        let joiner = read_string_u8(&mut buf)?.parse().ok()?;
+        let session_key = read_string_u8(&mut buf)?;
+
+        if buf.len() != 8 {
+            return None;
+        }
+
+        let mut expires = [0u8; 8];
+        expires.copy_from_slice(buf);
+
+        let ticket = MatchTicket { host, joiner, session_key, expires: u64::from_le_bytes(expires) };
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+
+        if ticket.expires < now {
+            return None;
+        }
+
+        Some(ticket)
+    }
+}
+
+pub struct TicketSigner {
+    signing_key: SigningKey
+}
+
+impl TicketSigner {
+    pub fn generate() -> TicketSigner {
+        let mut seed = [0u8; 32];
+        rand::thread_rng().fill(&mut seed);
+
+        TicketSigner { signing_key: SigningKey::from_bytes(&seed) }
+    }
+
+    // Loads the hex encoded 32 byte seed at `path`, or generates a new one
+    // there (and its public key at `<path>.pub`) if the file does not exist.
+    // The seed is only readable by its owner.
+    pub fn load_or_generate(path: &str) -> Result<TicketSigner, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => {
+                let seed = decode_seed(text.trim())
+                    .ok_or_else(|| format!("{}: expected 64 hex characters", path))?;
+
+                if is_shared(path) {
+                    warn!(path; "Server key can be read by other users, restrict it with `chmod 600`");
+                }
+
+                Ok(TicketSigner { signing_key: SigningKey::from_bytes(&seed) })
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let signer = TicketSigner::generate();
+
+                create_private(path)
+                    .and_then(|mut file| file.write_all(encode_hex(&signer.signing_key.to_bytes()).as_bytes()))
+                    .map_err(|e| format!("{}: {}", path, e))?;
+
+                let public_path = format!("{}.pub", path);
+                std::fs::write(&public_path, signer.public_key_hex())
+                    .map_err(|e| format!("{}: {}", public_path, e))?;
+
+                Ok(signer)
+            },
+            Err(e) => Err(format!("{}: {}", path, e))
+        }
+    }
+
+    pub fn public_key_hex(&self) -> String {
+        encode_hex(self.signing_key.verifying_key().as_bytes())
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        self.signing_key.verifying_key().to_bytes()
+    }
+
+    // Binds the server's ephemeral transport key to its identity, so a
+    // client that checks it against `server_key.pub` knows no one in the
+    // middle swapped the key
+    pub fn sign_key_exchange(&self, client_public_key: &[u8], server_public_key: &[u8]) -> [u8; SIGNATURE_LENGTH] {
+        let message = key_exchange_message(client_public_key, server_public_key);
+
+        self.signing_key.sign(&message).to_bytes()
+    }
+
+    pub fn sign(&self, ticket: &MatchTicket) -> Vec<u8> {
+        let mut buf = ticket.encode();
+        let signature = self.signing_key.sign(&buf);
+
+        buf.extend(&signature.to_bytes());
+        buf
+    }
+}
+
+// Checks the signature the server sent with its `KeyExchange` reply.
+// The signed message is `"matchmaker key exchange" || client public key || server public key`.
+pub fn verify_key_exchange(public_key: &[u8; 32], client_public_key: &[u8], server_public_key: &[u8], signature: &[u8]) -> bool {
+    let verifying_key = match VerifyingKey::from_bytes(public_key) {
+        Ok(verifying_key) => verifying_key,
+        Err(_) => return false
+    };
+
+    let signature = match Signature::from_slice(signature) {
+        Ok(signature) => signature,
+        Err(_) => return false
+    };
+
+    verifying_key.verify(&key_exchange_message(client_public_key, server_public_key), &signature).is_ok()
+}
+
+fn key_exchange_message(client_public_key: &[u8], server_public_key: &[u8]) -> Vec<u8> {
+    let mut message = KEY_EXCHANGE_CONTEXT.to_vec();
+    message.extend(client_public_key);
+    message.extend(server_public_key);
+    message
+}
+
+// Fails if the file already exists rather than reusing its permissions
+#[cfg(unix)]
+fn create_private(path: &str) -> std::io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &str) -> std::io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+// Readable or writable by the group or anyone
+#[cfg(unix)]
+fn is_shared(path: &str) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::metadata(path)
+        .map(|metadata| metadata.permissions().mode() & 0o077 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_shared(_path: &str) -> bool {
+    false
+}
+
+fn decode_seed(text: &str) -> Option<[u8; 32]> {
+    <[u8; 32]>::try_from(decode_hex(text)?.as_slice()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ticket() -> MatchTicket {
+        MatchTicket::new("203.0.113.1:5000".parse().unwrap(), "198.51.100.2:6000".parse().unwrap(), "abc1234")
+    }
+
+    fn public_key(signer: &TicketSigner) -> [u8; 32] {
+        signer.public_key()
+    }
+
+    #[test]
+    fn signed_tickets_verify() {
+        let signer = TicketSigner::generate();
+        let signed = signer.sign(&ticket());
+
+        assert_eq!(MatchTicket::verify(&public_key(&signer), &signed), Some(ticket()));
+    }
+
+    #[test]
+    fn any_changed_byte_fails() {
+        let signer = TicketSigner::generate();
+        let signed = signer.sign(&ticket());
+
+        for index in 0..signed.len() {
+            let mut changed = signed.clone();
+            changed[index] ^= 0x01;
+
+            assert_eq!(MatchTicket::verify(&public_key(&signer), &changed), None, "byte {} was changed", index);
+        }
+    }
+
+    #[test]
+    fn another_servers_key_fails() {
+        let signed = TicketSigner::generate().sign(&ticket());
+
+        assert_eq!(MatchTicket::verify(&public_key(&TicketSigner::generate()), &signed), None);
+    }
+
+    #[test]
+    fn truncated_tickets_fail() {
+        let signer = TicketSigner::generate();
+        let signed = signer.sign(&ticket());
+
+        for len in [0, 1, SIGNATURE_LENGTH - 1, SIGNATURE_LENGTH, signed.len() - 1] {
+            assert_eq!(MatchTicket::verify(&public_key(&signer), &signed[..len]), None, "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn expired_tickets_fail() {
+        let signer = TicketSigner::generate();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+
+        let expired = MatchTicket { expires: now - 1, ..ticket() };
+        assert_eq!(MatchTicket::verify(&public_key(&signer), &signer.sign(&expired)), None);
+    }
+
+    #[test]
+    fn key_exchanges_verify_only_for_the_keys_signed() {
+        let signer = TicketSigner::generate();
+        let signature = signer.sign_key_exchange(&[1; 32], &[2; 32]);
+
+        assert!(verify_key_exchange(&public_key(&signer), &[1; 32], &[2; 32], &signature));
+        assert!(!verify_key_exchange(&public_key(&signer), &[1; 32], &[3; 32], &signature));
+        assert!(!verify_key_exchange(&public_key(&signer), &[3; 32], &[2; 32], &signature));
+        assert!(!verify_key_exchange(&public_key(&TicketSigner::generate()), &[1; 32], &[2; 32], &signature));
+        assert!(!verify_key_exchange(&public_key(&signer), &[1; 32], &[2; 32], &signature[1..]));
+    }
+
+    #[test]
+    fn generated_keys_are_kept_private_and_reloaded() {
+        let path = std::env::temp_dir().join(format!("matchmaker-server-key-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let public_path = format!("{}.pub", path);
+
+        let generated = TicketSigner::load_or_generate(path).unwrap();
+        let loaded = TicketSigner::load_or_generate(path).unwrap();
+
+        assert_eq!(loaded.public_key_hex(), generated.public_key_hex());
+        assert_eq!(std::fs::read_to_string(&public_path).unwrap(), generated.public_key_hex());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = std::fs::metadata(path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+            assert!(!is_shared(path));
+
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o644)).unwrap();
+            assert!(is_shared(path));
+        }
+
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(&public_path).unwrap();
+    }
+}
@@ -0,0 +1,5 @@
+mod match_ticket;
+pub use match_ticket::{MatchTicket, TicketSigner, generate_match_key, verify_key_exchange};
+
+mod resume_token;
+pub use resume_token::{ResumeToken, generate_resume_token};
\ No newline at end of file
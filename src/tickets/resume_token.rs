@@ -0,0 +1,13 @@
+use rand::Rng;
+
+pub const RESUME_TOKEN_LEN: usize = 16;
+
+// Lets a client move its connection to a new address, for example after
+// its NAT mapping changes. Tokens are replaced every time they are used.
+pub type ResumeToken = [u8; RESUME_TOKEN_LEN];
+
+pub fn generate_resume_token() -> ResumeToken {
+    let mut token = [0u8; RESUME_TOKEN_LEN];
+    rand::thread_rng().fill(&mut token);
+    token
+}
@@ -0,0 +1,132 @@
+// Batched reads and writes on real loopback sockets. On Linux these go
+// through recvmmsg/sendmmsg, elsewhere through one syscall per datagram.
+
+use async_io::Async;
+use matchmaker::runtime::{BatchSender, Datagrams, Transport, BATCH_SIZE};
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+fn bind() -> UdpSocket {
+    UdpSocket::bind("127.0.0.1:0").unwrap()
+}
+
+// Reads until `count` datagrams have arrived
+fn receive_all(socket: &Async<UdpSocket>, datagrams: &mut Datagrams, count: usize) -> Vec<(SocketAddr, Vec<u8>)> {
+    let deadline = Instant::now() + TIMEOUT;
+    let mut received = Vec::new();
+
+    while received.len() < count {
+        assert!(Instant::now() < deadline, "only {} of {} datagrams arrived", received.len(), count);
+
+        async_std::task::block_on(datagrams.receive(socket)).unwrap();
+        received.extend(datagrams.iter().map(|(address, data)| (address, data.to_vec())));
+    }
+
+    received
+}
+
+#[test]
+fn every_datagram_is_read_in_order_with_its_sender() {
+    let server = Async::new(bind()).unwrap();
+    let server_address = server.get_ref().local_addr().unwrap();
+    let clients = [bind(), bind()];
+    let count = BATCH_SIZE * 2 + 5;
+
+    for index in 0..count {
+        let client = &clients[index % 2];
+        client.send_to(&(index as u32).to_le_bytes(), server_address).unwrap();
+    }
+
+    let mut datagrams = Datagrams::new(64);
+    let received = receive_all(&server, &mut datagrams, count);
+
+    for (client_index, client) in clients.iter().enumerate() {
+        let address = client.local_addr().unwrap();
+
+        let from_client: Vec<u32> = received
+            .iter()
+            .filter(|(from, _)| *from == address)
+            .map(|(_, data)| u32::from_le_bytes([data[0], data[1], data[2], data[3]]))
+            .collect();
+
+        let expected: Vec<u32> = (0..count as u32).filter(|index| *index as usize % 2 == client_index).collect();
+
+        assert_eq!(from_client, expected);
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn long_datagrams_are_left_empty() {
+    let server = Async::new(bind()).unwrap();
+    let client = bind();
+    let server_address = server.get_ref().local_addr().unwrap();
+
+    client.send_to(&[7; 100], server_address).unwrap();
+    client.send_to(&[8; 16], server_address).unwrap();
+
+    let mut datagrams = Datagrams::new(16);
+    let received = receive_all(&server, &mut datagrams, 2);
+
+    // the sender is still known, but none of the data is passed on
+    assert_eq!(received[0], (client.local_addr().unwrap(), Vec::new()));
+    assert_eq!(received[1].1, vec![8; 16]);
+}
+
+#[test]
+#[cfg(not(target_os = "linux"))]
+fn long_datagrams_are_cut_to_the_buffer_size() {
+    let server = Async::new(bind()).unwrap();
+    let client = bind();
+
+    client.send_to(&[7; 100], server.get_ref().local_addr().unwrap()).unwrap();
+
+    let mut datagrams = Datagrams::new(16);
+    let received = receive_all(&server, &mut datagrams, 1);
+
+    assert_eq!(received[0].1, vec![7; 16]);
+}
+
+#[test]
+fn queued_datagrams_are_sent_on_flush() {
+    let sender = BatchSender::new(bind());
+    let receiver = bind();
+    let receiver_address = receiver.local_addr().unwrap();
+    receiver.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+
+    for index in 0..3u8 {
+        sender.send_to(&[index; 10], receiver_address).unwrap();
+    }
+
+    let mut buf = [0; 64];
+    assert!(receiver.recv_from(&mut buf).is_err(), "nothing is sent before a flush");
+
+    sender.flush().unwrap();
+
+    for index in 0..3u8 {
+        let (length, _) = receiver.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..length], &[index; 10]);
+    }
+}
+
+#[test]
+fn a_full_queue_is_sent_without_a_flush() {
+    let sender = BatchSender::new(bind());
+    let receiver = bind();
+    let receiver_address = receiver.local_addr().unwrap();
+    receiver.set_read_timeout(Some(TIMEOUT)).unwrap();
+
+    // the datagram after a full batch pushes the batch out
+    for index in 0..=BATCH_SIZE {
+        sender.send_to(&[index as u8], receiver_address).unwrap();
+    }
+
+    let mut buf = [0; 64];
+
+    for index in 0..BATCH_SIZE {
+        let (length, _) = receiver.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..length], &[index as u8]);
+    }
+}
@@ -0,0 +1,459 @@
+// Runs a real server on a loopback port and talks to it with fake clients
+// that build every datagram by hand, so the tests also pin the wire format.
+
+#![allow(dead_code)]
+
+use matchmaker::attestation::BuildSecrets;
+use matchmaker::config::ServerConfig;
+use matchmaker::server::Server;
+use matchmaker::threads::ThreadMessage;
+use matchmaker::tickets::{verify_key_exchange, TicketSigner};
+use async_std::channel::{self, Sender};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use rand::Rng;
+use sha2::Sha256;
+use std::convert::TryInto;
+use std::net::{SocketAddr, UdpSocket};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+pub const CLIENT_HASH: &str = "test-build";
+
+// Client to server packet ids
+pub const PING_PONG: u16 = 0;
+pub const ACK: u16 = 1;
+pub const CREATE: u16 = 2;
+pub const JOIN: u16 = 3;
+pub const CLOSE: u16 = 4;
+pub const ERROR: u16 = 5;
+pub const CHALLENGE: u16 = 6;
+pub const ATTEST: u16 = 7;
+pub const KEY_EXCHANGE: u16 = 8;
+pub const NOTICE: u16 = 9;
+pub const RESUME: u16 = 10;
+pub const SESSION_EXPIRED: u16 = 11;
+
+// Packet id of a sealed client packet
+pub const SEALED: u16 = u16::MAX;
+
+// How long to wait for a reply before failing
+pub const TIMEOUT: Duration = Duration::from_secs(3);
+
+// Fast ticks so retransmits and timeouts happen quickly
+pub fn test_config() -> ServerConfig {
+    ServerConfig {
+        bind_address: String::from("127.0.0.1"),
+        tick_rate: 100.0,
+        max_silence_duration: 1.0,
+        max_ping_pong_rate: 0.25,
+        legacy_hashes: true,
+        snapshot_interval: 0.0,
+        shutdown_ack_timeout: 0.1,
+        ..ServerConfig::default()
+    }
+}
+
+pub struct TestServer {
+    pub address: SocketAddr,
+    // signs tickets and key exchanges
+    pub public_key: [u8; 32],
+    tx: Sender<ThreadMessage>,
+    handle: Option<JoinHandle<()>>
+}
+
+impl TestServer {
+    pub fn start() -> TestServer {
+        TestServer::start_with(test_config())
+    }
+
+    pub fn start_with(config: ServerConfig) -> TestServer {
+        TestServer::start_with_secrets(config, BuildSecrets::new())
+    }
+
+    // `secrets` holds `<build> <hex secret>` lines like secrets.txt
+    pub fn start_attesting(config: ServerConfig, secrets: &str) -> TestServer {
+        let path = std::env::temp_dir().join(format!("matchmaker-test-secrets-{}-{:?}.txt", std::process::id(), std::thread::current().id()));
+        std::fs::write(&path, secrets).unwrap();
+
+        let build_secrets = BuildSecrets::load(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        TestServer::start_with_secrets(config, build_secrets)
+    }
+
+    fn start_with_secrets(config: ServerConfig, build_secrets: BuildSecrets) -> TestServer {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = socket.local_addr().unwrap();
+        let (tx, rx) = channel::unbounded();
+        let server_tx = tx.clone();
+        let ticket_signer = TicketSigner::generate();
+        let public_key = ticket_signer.public_key();
+
+        let handle = std::thread::spawn(move || {
+            let mut server = Server::new(config);
+            server.use_ticket_signer(ticket_signer);
+            server.support_client_hashes(vec![String::from(CLIENT_HASH)]);
+            server.support_build_secrets(build_secrets);
+            server.restore_sessions();
+            async_std::task::block_on(Server::serve(&mut server, socket, server_tx, rx)).unwrap();
+        });
+
+        TestServer {
+            address,
+            public_key,
+            tx,
+            handle: Some(handle)
+        }
+    }
+
+    pub fn client(&self) -> FakeClient {
+        FakeClient::connect(self.address)
+    }
+
+    // Starts shutting down like on SIGTERM, without waiting for it
+    pub fn shutdown(&self) {
+        self.tx.try_send(ThreadMessage::Shutdown).unwrap();
+    }
+
+    pub fn has_stopped(&self) -> bool {
+        self.handle.as_ref().map(|handle| handle.is_finished()).unwrap_or(true)
+    }
+
+    // Runs a console command like the admin endpoint does
+    pub fn command(&self, command: &str) -> Result<String, String> {
+        let (reply_tx, reply_rx) = channel::bounded(1);
+
+        self.tx.try_send(ThreadMessage::Admin { command: command.to_string(), reply: reply_tx }).unwrap();
+
+        async_std::task::block_on(reply_rx.recv()).unwrap()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.tx.try_send(ThreadMessage::Shutdown);
+
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Reply {
+    Ack { id: u32 },
+    Data { id: u32, packet: u16, body: Vec<u8> }
+}
+
+pub struct FakeClient {
+    socket: UdpSocket,
+    server: SocketAddr,
+    next_id: u32,
+    // set by `exchange_keys`
+    sealing: Option<Sealing>,
+    // ack every data packet as it arrives, like a real client
+    pub auto_ack: bool
+}
+
+// One ChaCha20-Poly1305 key per direction, derived like the server does
+struct Sealing {
+    to_server: ChaCha20Poly1305,
+    from_server: ChaCha20Poly1305
+}
+
+// `[packet type, 0, 0, 0, packet id (u32 LE), 0, 0, 0, 0]`
+fn nonce(kind: u8, id: u32) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[0] = kind;
+    nonce[4..8].copy_from_slice(&id.to_le_bytes());
+    *Nonce::from_slice(&nonce)
+}
+
+impl FakeClient {
+    pub fn connect(server: SocketAddr) -> FakeClient {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+
+        FakeClient {
+            socket,
+            server,
+            next_id: 0,
+            sealing: None,
+            auto_ack: true
+        }
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.socket.local_addr().unwrap()
+    }
+
+    // Talks to a restarted server from the same address
+    pub fn switch_server(&mut self, server: SocketAddr) {
+        self.server = server;
+    }
+
+    // `[id u32 LE][packet u16 LE][fields]`, returns the id used
+    pub fn send(&mut self, packet: u16, fields: &[u8]) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.send_with_id(id, packet, fields);
+
+        id
+    }
+
+    // Sealed after `exchange_keys`
+    pub fn send_with_id(&self, id: u32, packet: u16, fields: &[u8]) {
+        let data = match &self.sealing {
+            Some(sealing) => {
+                let header = encode(id, SEALED, &[]);
+                let plaintext = encode_packet(packet, fields);
+                let sealed = sealing.to_server.encrypt(&nonce(1, id), Payload { msg: &plaintext, aad: &header }).unwrap();
+
+                [header, sealed].concat()
+            },
+            None => encode(id, packet, fields)
+        };
+
+        self.socket.send_to(&data, self.server).unwrap();
+    }
+
+    // Skips the sealing, as a client that lost its keys would
+    pub fn send_plaintext(&mut self, packet: u16, fields: &[u8]) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.socket.send_to(&encode(id, packet, fields), self.server).unwrap();
+
+        id
+    }
+
+    // Sends an ephemeral X25519 key, checks the signed reply against
+    // `server_key` and seals everything from then on
+    pub fn exchange_keys(&mut self, server_key: &[u8; 32]) {
+        let mut seed = [0u8; 32];
+        rand::thread_rng().fill(&mut seed);
+
+        let secret = x25519_dalek::StaticSecret::from(seed);
+        let public_key = x25519_dalek::PublicKey::from(&secret);
+
+        self.send(KEY_EXCHANGE, &bytes_u8(public_key.as_bytes()));
+
+        let (id, body) = self.expect_data_within(KEY_EXCHANGE, TIMEOUT).expect("no key exchange reply");
+
+        let mut reply = Reader::new(body);
+        let server_public_key = reply.bytes();
+        let signature = reply.bytes();
+
+        assert!(verify_key_exchange(server_key, public_key.as_bytes(), &server_public_key, &signature), "key exchange signature");
+
+        let server_public_key: [u8; 32] = server_public_key.as_slice().try_into().unwrap();
+        let shared = secret.diffie_hellman(&x25519_dalek::PublicKey::from(server_public_key));
+
+        let salt = [public_key.as_bytes().as_ref(), &server_public_key].concat();
+        let hkdf = Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes());
+
+        let mut to_server = [0u8; 32];
+        let mut from_server = [0u8; 32];
+        hkdf.expand(b"matchmaker client to server", &mut to_server).unwrap();
+        hkdf.expand(b"matchmaker server to client", &mut from_server).unwrap();
+
+        self.sealing = Some(Sealing {
+            to_server: ChaCha20Poly1305::new(Key::from_slice(&to_server)),
+            from_server: ChaCha20Poly1305::new(Key::from_slice(&from_server))
+        });
+
+        // the plaintext ack sent by `recv` came after the exchange and was dropped
+        self.ack(id);
+    }
+
+    pub fn pong(&mut self) -> u32 {
+        self.send(PING_PONG, &[])
+    }
+
+    pub fn ack(&mut self, id: u32) -> u32 {
+        self.send(ACK, &id.to_le_bytes())
+    }
+
+    pub fn create(&mut self, password_protected: bool) -> u32 {
+        let mut fields = string_u8(CLIENT_HASH);
+        fields.push(password_protected as u8);
+
+        self.send(CREATE, &fields)
+    }
+
+    pub fn join(&mut self, session_key: &str) -> u32 {
+        let mut fields = string_u8(CLIENT_HASH);
+        fields.extend(string_u8(session_key));
+
+        self.send(JOIN, &fields)
+    }
+
+    pub fn close(&mut self) -> u32 {
+        self.send(CLOSE, &[])
+    }
+
+    pub fn recv(&mut self, timeout: Duration) -> Option<Reply> {
+        let deadline = Instant::now() + timeout;
+        let mut buf = [0; 1024];
+
+        while Instant::now() < deadline {
+            let len = match self.socket.recv(&mut buf) {
+                Ok(len) => len,
+                Err(_) => continue
+            };
+
+            let reply = match &self.sealing {
+                Some(sealing) => open_reply(sealing, &buf[..len]),
+                None => parse_reply(&buf[..len])
+            };
+
+            if let Reply::Data { id, .. } = reply {
+                if self.auto_ack {
+                    self.ack(id);
+                }
+            }
+
+            return Some(reply);
+        }
+
+        None
+    }
+
+    // Waits for a data packet of the given type, skipping anything else
+    pub fn expect(&mut self, packet: u16) -> Reader {
+        self.expect_within(packet, TIMEOUT)
+            .unwrap_or_else(|| panic!("no packet {} within {:?}", packet, TIMEOUT))
+    }
+
+    pub fn expect_within(&mut self, packet: u16, timeout: Duration) -> Option<Reader> {
+        self.expect_data_within(packet, timeout).map(|(_, body)| Reader::new(body))
+    }
+
+    // Like `expect_within` but also returns the packet id
+    pub fn expect_data_within(&mut self, packet: u16, timeout: Duration) -> Option<(u32, Vec<u8>)> {
+        let deadline = Instant::now() + timeout;
+
+        while Instant::now() < deadline {
+            if let Some(Reply::Data { id, packet: received, body }) = self.recv(deadline - Instant::now()) {
+                if received == packet {
+                    return Some((id, body));
+                }
+            }
+        }
+
+        None
+    }
+
+    // Creates a session and returns its key
+    pub fn host(&mut self, password_protected: bool) -> String {
+        self.create(password_protected);
+        self.expect(CREATE).string()
+    }
+}
+
+// `[id u32 LE][packet u16 LE][fields]`
+pub fn encode(id: u32, packet: u16, fields: &[u8]) -> Vec<u8> {
+    let mut data = id.to_le_bytes().to_vec();
+    data.extend(encode_packet(packet, fields));
+    data
+}
+
+// `[packet u16 LE][fields]`, the part that is sealed
+fn encode_packet(packet: u16, fields: &[u8]) -> Vec<u8> {
+    let mut data = packet.to_le_bytes().to_vec();
+    data.extend(fields);
+    data
+}
+
+// `[packet type | 0x80][id u32][sealed [packet u16][fields]]`,
+// where an ack's id is the acknowledged one. Packets sent before the
+// exchange are resent in plaintext.
+fn open_reply(sealing: &Sealing, data: &[u8]) -> Reply {
+    if data[0] & 0x80 == 0 {
+        return parse_reply(data);
+    }
+
+    let kind = data[0] & 0x7f;
+    let id = u32::from_le_bytes(data[1..5].try_into().unwrap());
+    let payload = Payload { msg: &data[5..], aad: &data[..5] };
+    let plaintext = sealing.from_server.decrypt(&nonce(kind, id), payload).expect("sealed packet did not open");
+
+    match kind {
+        0 => Reply::Ack { id },
+        _ => Reply::Data {
+            id,
+            packet: u16::from_le_bytes(plaintext[0..2].try_into().unwrap()),
+            body: plaintext[2..].to_vec()
+        }
+    }
+}
+
+pub fn parse_reply(data: &[u8]) -> Reply {
+    match data[0] {
+        // `[0][ack packet u16][acked id u32]`
+        0 => Reply::Ack {
+            id: u32::from_le_bytes(data[3..7].try_into().unwrap())
+        },
+        // `[1][id u32][packet u16][fields]`
+        1 => Reply::Data {
+            id: u32::from_le_bytes(data[1..5].try_into().unwrap()),
+            packet: u16::from_le_bytes(data[5..7].try_into().unwrap()),
+            body: data[7..].to_vec()
+        },
+        packet_type => panic!("unexpected packet type {}", packet_type)
+    }
+}
+
+pub fn string_u8(value: &str) -> Vec<u8> {
+    bytes_u8(value.as_bytes())
+}
+
+pub fn bytes_u8(value: &[u8]) -> Vec<u8> {
+    let mut data = vec![value.len() as u8];
+    data.extend(value);
+    data
+}
+
+// Reads the fields of a server packet in order
+pub struct Reader {
+    body: Vec<u8>,
+    position: usize
+}
+
+impl Reader {
+    pub fn new(body: Vec<u8>) -> Reader {
+        Reader { body, position: 0 }
+    }
+
+    pub fn u8(&mut self) -> u8 {
+        self.position += 1;
+        self.body[self.position - 1]
+    }
+
+    pub fn bool(&mut self) -> bool {
+        self.u8() != 0
+    }
+
+    pub fn u16(&mut self) -> u16 {
+        self.position += 2;
+        u16::from_le_bytes(self.body[self.position - 2..self.position].try_into().unwrap())
+    }
+
+    pub fn u32(&mut self) -> u32 {
+        self.position += 4;
+        u32::from_le_bytes(self.body[self.position - 4..self.position].try_into().unwrap())
+    }
+
+    pub fn bytes(&mut self) -> Vec<u8> {
+        let len = self.u8() as usize;
+        self.position += len;
+        self.body[self.position - len..self.position].to_vec()
+    }
+
+    pub fn string(&mut self) -> String {
+        String::from_utf8(self.bytes()).unwrap()
+    }
+}
@@ -0,0 +1,679 @@
+mod common;
+
+use common::*;
+use matchmaker::config::ServerConfig;
+use std::time::{Duration, Instant};
+
+#[test]
+fn create_replies_with_a_session_key() {
+    let server = TestServer::start();
+    let mut host = server.client();
+
+    let key = host.host(false);
+
+    assert_eq!(key.len(), 7);
+    assert!(key.chars().all(|c| c.is_ascii_alphanumeric()));
+}
+
+#[test]
+fn unknown_hash_is_refused() {
+    let server = TestServer::start();
+    let mut client = server.client();
+
+    let mut fields = string_u8("not-a-build");
+    fields.push(0);
+    let id = client.send(CREATE, &fields);
+
+    let mut error = client.expect(ERROR);
+    assert_eq!(error.u32(), id);
+    assert_eq!(error.string(), "Client is not attested");
+    assert_eq!(error.u16(), 1);
+}
+
+#[test]
+fn public_join_matches_the_open_session() {
+    let server = TestServer::start();
+    let mut host = server.client();
+    let mut joiner = server.client();
+
+    host.host(false);
+    joiner.join("");
+
+    let mut joined = joiner.expect(JOIN);
+    assert!(joined.bool());
+    assert_eq!(joined.string(), host.address().to_string());
+    let joiner_ticket = joined.bytes();
+    let joiner_key = joined.bytes();
+
+    let mut hosted = host.expect(JOIN);
+    assert!(hosted.bool());
+    assert_eq!(hosted.string(), joiner.address().to_string());
+    assert_eq!(hosted.bytes(), joiner_ticket);
+    assert_eq!(hosted.bytes(), joiner_key);
+
+    assert!(!joiner_ticket.is_empty());
+    assert_eq!(joiner_key.len(), 32);
+}
+
+#[test]
+fn public_joins_take_the_oldest_session_first() {
+    let server = TestServer::start();
+    let mut hosts: Vec<FakeClient> = (0..3).map(|_| server.client()).collect();
+
+    for host in &mut hosts {
+        host.host(false);
+    }
+
+    for host in &hosts[..2] {
+        let mut joiner = server.client();
+        joiner.join("");
+
+        let mut joined = joiner.expect(JOIN);
+        assert!(joined.bool());
+        assert_eq!(joined.string(), host.address().to_string());
+    }
+}
+
+#[test]
+fn private_session_is_only_joined_by_key() {
+    let server = TestServer::start();
+    let mut host = server.client();
+    let mut joiner = server.client();
+
+    let key = host.host(true);
+
+    joiner.join("");
+    assert!(!joiner.expect(JOIN).bool());
+
+    joiner.join(&key);
+    let mut joined = joiner.expect(JOIN);
+    assert!(joined.bool());
+    assert_eq!(joined.string(), host.address().to_string());
+
+    assert!(host.expect(JOIN).bool());
+}
+
+#[test]
+fn join_with_an_unknown_key_fails() {
+    let server = TestServer::start();
+    let mut host = server.client();
+    let mut joiner = server.client();
+
+    host.host(false);
+    joiner.join("nokey12");
+
+    assert!(!joiner.expect(JOIN).bool());
+}
+
+#[test]
+fn closed_session_cannot_be_joined() {
+    let server = TestServer::start();
+    let mut host = server.client();
+    let mut joiner = server.client();
+
+    let key = host.host(false);
+    host.close();
+
+    // the close is handled once the server has acked it
+    while let Some(reply) = host.recv(TIMEOUT) {
+        if let Reply::Ack { .. } = reply {
+            break;
+        }
+    }
+
+    joiner.join(&key);
+    assert!(!joiner.expect(JOIN).bool());
+
+    joiner.join("");
+    assert!(!joiner.expect(JOIN).bool());
+}
+
+#[test]
+fn silent_client_is_kicked() {
+    let server = TestServer::start();
+    let mut client = server.client();
+    client.auto_ack = false;
+
+    client.pong();
+
+    let mut close = client.expect(CLOSE);
+    assert_eq!(close.string(), "Dropped due to silence");
+}
+
+#[test]
+fn answering_pings_keeps_a_client_connected() {
+    let server = TestServer::start();
+    let mut client = server.client();
+
+    client.pong();
+
+    // auto acks count as traffic, so the silence window never runs out
+    let close = client.expect_within(CLOSE, Duration::from_secs(2));
+    assert!(close.is_none());
+}
+
+#[test]
+fn unacknowledged_packets_are_resent_until_acked() {
+    let server = TestServer::start();
+    let mut host = server.client();
+    host.auto_ack = false;
+
+    host.create(false);
+
+    let (id, body) = host.expect_data_within(CREATE, TIMEOUT).expect("no create reply");
+    let (resent_id, resent_body) = host.expect_data_within(CREATE, TIMEOUT).expect("create reply was not resent");
+
+    assert_eq!(resent_id, id);
+    assert_eq!(resent_body, body);
+
+    host.ack(id);
+
+    // anything still in flight when the ack arrived may show up once more
+    host.expect_data_within(CREATE, Duration::from_millis(100));
+
+    assert!(host.expect_data_within(CREATE, Duration::from_millis(500)).is_none());
+}
+
+#[test]
+fn every_packet_is_acked() {
+    let server = TestServer::start();
+    let mut client = server.client();
+
+    let id = client.pong();
+
+    loop {
+        match client.recv(TIMEOUT).expect("no ack") {
+            Reply::Ack { id: acked } => {
+                assert_eq!(acked, id);
+                break;
+            },
+            Reply::Data { .. } => continue
+        }
+    }
+}
+
+fn sharded_config() -> ServerConfig {
+    ServerConfig {
+        worker_threads: 4,
+        ..test_config()
+    }
+}
+
+#[test]
+fn sharded_server_matches_clients() {
+    let server = TestServer::start_with(sharded_config());
+    let mut hosts: Vec<FakeClient> = (0..4).map(|_| server.client()).collect();
+
+    for host in &mut hosts {
+        host.host(false);
+    }
+
+    for host in &mut hosts {
+        let mut joiner = server.client();
+        joiner.join("");
+
+        let mut joined = joiner.expect(JOIN);
+        assert!(joined.bool());
+        assert_eq!(joined.string(), host.address().to_string());
+
+        let mut hosted = host.expect(JOIN);
+        assert!(hosted.bool());
+        assert_eq!(hosted.string(), joiner.address().to_string());
+    }
+}
+
+#[test]
+fn sharded_server_resends_and_kicks_silent_clients() {
+    let server = TestServer::start_with(sharded_config());
+    let mut host = server.client();
+    host.auto_ack = false;
+
+    host.create(false);
+
+    let (id, _) = host.expect_data_within(CREATE, TIMEOUT).expect("no create reply");
+    let (resent_id, _) = host.expect_data_within(CREATE, TIMEOUT).expect("create reply was not resent");
+    assert_eq!(resent_id, id);
+
+    let mut close = host.expect(CLOSE);
+    assert_eq!(close.string(), "Dropped due to silence");
+}
+
+#[test]
+fn sharded_server_keeps_packets_sent_before_the_first_reply() {
+    let server = TestServer::start_with(sharded_config());
+
+    // kept open so no two get the same port
+    let mut hosts: Vec<FakeClient> = (0..16).map(|_| server.client()).collect();
+
+    for host in &mut hosts {
+        // the shard only gets the link once the server has seen the first packet
+        host.pong();
+        host.create(false);
+
+        for _ in 0..8 {
+            host.pong();
+        }
+
+        assert_eq!(host.expect(CREATE).string().len(), 7);
+    }
+}
+
+#[test]
+fn sharded_server_moves_resumed_sessions() {
+    let server = TestServer::start_with(sharded_config());
+    let mut host = server.client();
+    let mut joiner = server.client();
+
+    // the token is sent before the create reply
+    host.create(true);
+    let token = host.expect(RESUME).bytes();
+    let key = host.expect(CREATE).string();
+    let last_id = host.pong();
+
+    // the same client at a new address, carrying on from its last packet id
+    let mut moved = server.client();
+    let mut fields = vec![token.len() as u8];
+    fields.extend(&token);
+    moved.send_with_id(last_id + 1, RESUME, &fields);
+    moved.expect(RESUME);
+
+    joiner.join(&key);
+
+    let mut joined = joiner.expect(JOIN);
+    assert!(joined.bool());
+    assert_eq!(joined.string(), moved.address().to_string());
+
+    let mut hosted = moved.expect(JOIN);
+    assert!(hosted.bool());
+    assert_eq!(hosted.string(), joiner.address().to_string());
+}
+
+#[test]
+fn sharded_server_seals_clients_and_waits_for_their_close_acks() {
+    let config = ServerConfig {
+        shutdown_drain_time: 0.0,
+        shutdown_ack_timeout: 5.0,
+        ..sharded_config()
+    };
+
+    let server = TestServer::start_with(config);
+    let mut host = server.client();
+    host.exchange_keys(&server.public_key);
+
+    let key = host.host(false);
+    let mut joiner = server.client();
+    joiner.join(&key);
+    assert!(joiner.expect(JOIN).bool());
+    assert!(host.expect(JOIN).bool());
+
+    assert!(server.command("clients").unwrap().contains("encrypted=true"));
+
+    host.auto_ack = false;
+    server.shutdown();
+
+    let (close_id, _) = host.expect_data_within(CLOSE, TIMEOUT).expect("no close");
+    joiner.expect(CLOSE);
+
+    assert!(host.expect_within(CLOSE, Duration::from_millis(300)).is_some(), "unacknowledged close was not resent");
+    assert!(!server.has_stopped());
+
+    host.ack(close_id);
+
+    let deadline = Instant::now() + TIMEOUT;
+
+    while !server.has_stopped() {
+        assert!(Instant::now() < deadline, "server kept waiting after every close was acked");
+        host.recv(Duration::from_millis(20));
+    }
+}
+
+fn attest_mac(secret: &[u8], nonce: &[u8]) -> Vec<u8> {
+    use hmac::{Hmac, Mac};
+
+    let mut hmac = Hmac::<sha2::Sha256>::new_from_slice(secret).unwrap();
+    hmac.update(nonce);
+    hmac.finalize().into_bytes().to_vec()
+}
+
+fn attest_fields(build: &str, mac: &[u8]) -> Vec<u8> {
+    let mut fields = string_u8(build);
+    fields.push(mac.len() as u8);
+    fields.extend(mac);
+    fields
+}
+
+const SECRETS: &str = "test-build 00ff10\n";
+const SECRET: [u8; 3] = [0x00, 0xff, 0x10];
+
+// Answers the challenge the server sent, returns whether it was accepted
+fn attest(client: &mut FakeClient) -> bool {
+    let nonce = client.expect(CHALLENGE).bytes();
+    client.send(ATTEST, &attest_fields(CLIENT_HASH, &attest_mac(&SECRET, &nonce)));
+    client.expect(ATTEST).bool()
+}
+
+// `Error` carries the id it answers, a message and then the code
+fn expect_error(client: &mut FakeClient, id: u32) -> u16 {
+    let mut error = client.expect(ERROR);
+    assert_eq!(error.u32(), id);
+    error.string();
+    error.u16()
+}
+
+#[test]
+fn clients_must_attest_with_the_current_nonce() {
+    let config = ServerConfig {
+        legacy_hashes: false,
+        ..test_config()
+    };
+
+    let server = TestServer::start_attesting(config, SECRETS);
+    let mut client = server.client();
+
+    // refused before attesting, even with a known hash
+    let id = client.create(false);
+    let first_nonce = client.expect(CHALLENGE).bytes();
+    assert_eq!(expect_error(&mut client, id), 1);
+
+    // a bad mac fails and brings a fresh nonce
+    let id = client.send(ATTEST, &attest_fields("test-build", &[0; 32]));
+    assert!(!client.expect(ATTEST).bool());
+    assert_eq!(expect_error(&mut client, id), 2);
+    let second_nonce = client.expect(CHALLENGE).bytes();
+    assert_ne!(second_nonce, first_nonce);
+
+    let id = client.create(false);
+    assert_eq!(expect_error(&mut client, id), 1);
+
+    // the first nonce was used up by the failed attempt
+    let id = client.send(ATTEST, &attest_fields("test-build", &attest_mac(&SECRET, &first_nonce)));
+    assert!(!client.expect(ATTEST).bool());
+    assert_eq!(expect_error(&mut client, id), 2);
+    let third_nonce = client.expect(CHALLENGE).bytes();
+
+    // the second one too, now that a new one was sent
+    let id = client.send(ATTEST, &attest_fields("test-build", &attest_mac(&SECRET, &second_nonce)));
+    assert!(!client.expect(ATTEST).bool());
+    assert_eq!(expect_error(&mut client, id), 2);
+    let nonce = client.expect(CHALLENGE).bytes();
+    assert_ne!(nonce, third_nonce);
+
+    client.send(ATTEST, &attest_fields("test-build", &attest_mac(&SECRET, &nonce)));
+    assert!(client.expect(ATTEST).bool());
+
+    let key = client.host(false);
+    assert_eq!(key.len(), 7);
+}
+
+#[test]
+fn sealed_packets_are_answered_and_plaintext_is_dropped_after_the_key_exchange() {
+    let server = TestServer::start();
+    let mut host = server.client();
+    host.exchange_keys(&server.public_key);
+
+    let mut fields = string_u8(CLIENT_HASH);
+    fields.push(0);
+    let id = host.send_plaintext(CREATE, &fields);
+
+    // neither acked nor answered
+    let deadline = Instant::now() + Duration::from_millis(300);
+
+    while let Some(reply) = host.recv(deadline.saturating_duration_since(Instant::now())) {
+        match reply {
+            Reply::Ack { id: acked } => assert_ne!(acked, id, "plaintext packet was acked"),
+            Reply::Data { packet, .. } => assert_ne!(packet, CREATE, "plaintext packet was answered")
+        }
+    }
+
+    let key = host.host(false);
+    assert_eq!(key.len(), 7);
+
+    // a plaintext joiner still matches with the sealed host
+    let mut joiner = server.client();
+    joiner.join(&key);
+    assert!(joiner.expect(JOIN).bool());
+    assert!(host.expect(JOIN).bool());
+}
+
+#[test]
+fn closing_a_session_from_the_console_keeps_its_host_connected() {
+    let server = TestServer::start();
+    let mut host = server.client();
+    let key = host.host(false);
+
+    assert!(server.command(&format!("close {}", key)).is_ok());
+    assert_eq!(host.expect(SESSION_EXPIRED).string(), key);
+
+    let mut joiner = server.client();
+    joiner.join(&key);
+    assert!(!joiner.expect(JOIN).bool());
+
+    // still connected, so it can host again straight away
+    let key = host.host(false);
+    joiner.join(&key);
+    assert!(joiner.expect(JOIN).bool());
+
+    assert!(server.command("close nosuch1").is_err());
+}
+
+#[test]
+fn saved_sessions_are_restored_and_their_hosts_challenged_again() {
+    let path = std::env::temp_dir().join(format!("matchmaker-test-sessions-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let config = ServerConfig {
+        legacy_hashes: false,
+        snapshot_interval: 0.05,
+        sessions_path: path.to_str().unwrap().to_string(),
+        ..test_config()
+    };
+
+    let server = TestServer::start_attesting(config.clone(), SECRETS);
+    let mut host = server.client();
+    host.pong();
+    assert!(attest(&mut host));
+    let key = host.host(false);
+
+    let deadline = Instant::now() + TIMEOUT;
+
+    while !std::fs::read_to_string(&path).map(|text| text.contains(&key)).unwrap_or(false) {
+        assert!(Instant::now() < deadline, "session was not saved");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    drop(server);
+
+    let server = TestServer::start_attesting(config, SECRETS);
+    host.switch_server(server.address);
+
+    // the restarted server asks the host to prove its build again
+    host.expect(PING_PONG);
+    let nonce = host.expect(CHALLENGE).bytes();
+
+    let mut joiner = server.client();
+    joiner.pong();
+    assert!(attest(&mut joiner));
+    joiner.join(&key);
+    assert!(joiner.expect(JOIN).bool());
+    assert!(host.expect(JOIN).bool());
+
+    // the attestation from before the restart is gone
+    let id = host.create(false);
+    assert_eq!(expect_error(&mut host, id), 1);
+
+    host.send(ATTEST, &attest_fields(CLIENT_HASH, &attest_mac(&SECRET, &nonce)));
+    assert!(host.expect(ATTEST).bool());
+    assert_eq!(host.host(false).len(), 7);
+
+    drop(server);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn maintenance_refuses_sessions_and_notices_reach_every_client() {
+    let config = ServerConfig {
+        maintenance: true,
+        maintenance_message: String::from("Back soon"),
+        motd: String::from("Welcome"),
+        ..test_config()
+    };
+
+    let server = TestServer::start_with(config);
+    let mut host = server.client();
+
+    let id = host.create(false);
+    assert_eq!(host.expect(NOTICE).string(), "Welcome");
+
+    let mut error = host.expect(ERROR);
+    assert_eq!(error.u32(), id);
+    assert_eq!(error.string(), "Back soon");
+    assert_eq!(error.u16(), 6);
+
+    let mut joiner = server.client();
+    let id = joiner.join("abc1234");
+    assert_eq!(joiner.expect(NOTICE).string(), "Welcome");
+    assert_eq!(expect_error(&mut joiner, id), 6);
+
+    assert!(server.command("maintenance off").is_ok());
+    let key = host.host(false);
+
+    assert_eq!(server.command("notice Restarting in 5 minutes"), Ok(String::from("Sent notice to 2 clients")));
+    assert_eq!(host.expect(NOTICE).string(), "Restarting in 5 minutes");
+    assert_eq!(joiner.expect(NOTICE).string(), "Restarting in 5 minutes");
+
+    // joins are refused too, even into sessions opened before
+    assert!(server.command("maintenance on").is_ok());
+    let id = joiner.join(&key);
+    assert_eq!(expect_error(&mut joiner, id), 6);
+}
+
+#[test]
+fn sessions_expire_after_max_session_age_and_their_host_stays() {
+    let config = ServerConfig {
+        max_session_age: 0.3,
+        ..test_config()
+    };
+
+    let server = TestServer::start_with(config);
+    let mut host = server.client();
+
+    let created = Instant::now();
+    let key = host.host(false);
+
+    assert_eq!(host.expect(SESSION_EXPIRED).string(), key);
+    assert!(created.elapsed() >= Duration::from_millis(300));
+
+    let mut joiner = server.client();
+    joiner.join(&key);
+    assert!(!joiner.expect(JOIN).bool());
+
+    let key = host.host(false);
+    joiner.join(&key);
+    assert!(joiner.expect(JOIN).bool());
+}
+
+#[test]
+fn sessions_expire_after_max_waiting_time_without_host_activity() {
+    let config = ServerConfig {
+        max_waiting_time: 0.8,
+        ..test_config()
+    };
+
+    let server = TestServer::start_with(config);
+    let mut host = server.client();
+    let key = host.host(false);
+
+    // acks of pings don't count as activity, anything else does
+    assert!(host.expect_within(SESSION_EXPIRED, Duration::from_millis(500)).is_none());
+    let active = Instant::now();
+    host.send(ATTEST, &attest_fields(CLIENT_HASH, &[0; 32]));
+
+    assert!(host.expect_within(SESSION_EXPIRED, Duration::from_millis(600)).is_none());
+
+    assert_eq!(host.expect(SESSION_EXPIRED).string(), key);
+    assert!(active.elapsed() >= Duration::from_millis(800));
+}
+
+#[test]
+fn shutting_down_drains_sessions_then_waits_for_close_acks() {
+    let config = ServerConfig {
+        shutdown_drain_time: 5.0,
+        shutdown_ack_timeout: 5.0,
+        ..test_config()
+    };
+
+    let server = TestServer::start_with(config);
+    let mut host = server.client();
+    let key = host.host(false);
+
+    server.shutdown();
+
+    // open sessions can still be joined, new ones are refused
+    let mut late = server.client();
+    let id = late.create(false);
+    assert_eq!(expect_error(&mut late, id), 5);
+
+    let mut joiner = server.client();
+    joiner.join(&key);
+    assert!(joiner.expect(JOIN).bool());
+    assert!(host.expect(JOIN).bool());
+
+    // the last match ends the drain and every client is closed
+    host.auto_ack = false;
+    let (close_id, body) = host.expect_data_within(CLOSE, TIMEOUT).expect("no close");
+    assert_eq!(Reader::new(body).string(), "Server shutting down");
+    joiner.expect(CLOSE);
+    late.expect(CLOSE);
+
+    assert!(host.expect_within(CLOSE, Duration::from_millis(300)).is_some(), "unacknowledged close was not resent");
+    assert!(!server.has_stopped());
+
+    host.ack(close_id);
+    host.auto_ack = true;
+
+    let deadline = Instant::now() + TIMEOUT;
+
+    while !server.has_stopped() {
+        assert!(Instant::now() < deadline, "server kept waiting after every close was acked");
+        host.recv(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn resume_moves_a_client_once_per_token() {
+    let server = TestServer::start();
+    let mut host = server.client();
+
+    host.create(true);
+    let token = host.expect(RESUME).bytes();
+    let key = host.expect(CREATE).string();
+    let last_id = host.pong();
+
+    let mut moved = server.client();
+    moved.send_with_id(last_id + 1, RESUME, &bytes_u8(&token));
+    let new_token = moved.expect(RESUME).bytes();
+    assert_ne!(new_token, token);
+
+    // a used token is unknown, so its sender is a new client with nothing to resume
+    let mut replayed = server.client();
+    let id = replayed.send(RESUME, &bytes_u8(&token));
+    assert_eq!(expect_error(&mut replayed, id), 7);
+
+    // so is a connected client resuming where it already is
+    moved.send_with_id(last_id + 2, RESUME, &bytes_u8(&new_token));
+    assert_eq!(expect_error(&mut moved, last_id + 2), 7);
+
+    let mut joiner = server.client();
+    joiner.join(&key);
+
+    let mut joined = joiner.expect(JOIN);
+    assert!(joined.bool());
+    assert_eq!(joined.string(), moved.address().to_string());
+    assert!(moved.expect(JOIN).bool());
+}
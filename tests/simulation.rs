@@ -0,0 +1,294 @@
+// Runs the server against a virtual clock and a lossy simulated network.
+// Each run only depends on its seed, so failures reproduce exactly.
+
+mod common;
+
+use common::*;
+use matchmaker::config::ServerConfig;
+use matchmaker::simulation::{NetworkConditions, Simulation};
+use std::collections::{HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+const RESEND_DELAY: Duration = Duration::from_millis(50);
+
+fn lossy() -> NetworkConditions {
+    NetworkConditions {
+        loss: 0.2,
+        duplication: 0.1,
+        reordering: 0.1,
+        latency: Duration::from_millis(30),
+        jitter: Duration::from_millis(20)
+    }
+}
+
+fn simulation_config() -> ServerConfig {
+    ServerConfig {
+        max_silence_duration: 5.0,
+        ..test_config()
+    }
+}
+
+fn simulation(seed: u64, conditions: NetworkConditions) -> Simulation {
+    let mut simulation = Simulation::new(simulation_config(), seed, conditions);
+    simulation.server().support_client_hashes(vec![String::from(CLIENT_HASH)]);
+    simulation
+}
+
+// The server drops packets older than the newest it has seen, so this client
+// keeps one packet in flight and resends it until it is acked
+struct SimClient {
+    address: SocketAddr,
+    next_id: u32,
+    queued: VecDeque<(u16, Vec<u8>)>,
+    in_flight: Option<(u32, Vec<u8>)>,
+    last_sent: Option<Duration>,
+    // data packet ids already received, the server resends until acked
+    seen: HashSet<u32>,
+    received: Vec<(u16, Reader)>
+}
+
+impl SimClient {
+    fn new(address: &str) -> SimClient {
+        SimClient {
+            address: address.parse().unwrap(),
+            next_id: 0,
+            queued: VecDeque::new(),
+            in_flight: None,
+            last_sent: None,
+            seen: HashSet::new(),
+            received: Vec::new()
+        }
+    }
+
+    fn queue(&mut self, packet: u16, fields: Vec<u8>) {
+        self.queued.push_back((packet, fields));
+    }
+
+    fn create(&mut self, password_protected: bool) {
+        let mut fields = string_u8(CLIENT_HASH);
+        fields.push(password_protected as u8);
+        self.queue(CREATE, fields);
+    }
+
+    fn join(&mut self, session_key: &str) {
+        let mut fields = string_u8(CLIENT_HASH);
+        fields.extend(string_u8(session_key));
+        self.queue(JOIN, fields);
+    }
+
+    fn take(&mut self, packet: u16) -> Option<Reader> {
+        let index = self.received.iter().position(|(received, _)| *received == packet)?;
+        Some(self.received.remove(index).1)
+    }
+
+    // Reads what was delivered and sends or resends the next packet
+    fn update(&mut self, simulation: &mut Simulation) {
+        while let Some(data) = simulation.recv(self.address) {
+            match parse_reply(&data) {
+                Reply::Ack { id } => {
+                    if self.in_flight.as_ref().map(|(in_flight, _)| *in_flight == id).unwrap_or(false) {
+                        self.in_flight = None;
+                        self.last_sent = None;
+                    }
+                },
+                Reply::Data { id, packet, body } => {
+                    if self.seen.insert(id) {
+                        self.queue(ACK, id.to_le_bytes().to_vec());
+                        self.received.push((packet, Reader::new(body)));
+                    }
+                }
+            }
+        }
+
+        if self.in_flight.is_none() {
+            if let Some((packet, fields)) = self.queued.pop_front() {
+                self.in_flight = Some((self.next_id, encode(self.next_id, packet, &fields)));
+                self.next_id += 1;
+            }
+        }
+
+        let resend_due = self.last_sent
+            .map(|last_sent| simulation.elapsed() >= last_sent + RESEND_DELAY)
+            .unwrap_or(true);
+
+        if let (Some((_, data)), true) = (&self.in_flight, resend_due) {
+            simulation.send(self.address, data);
+            self.last_sent = Some(simulation.elapsed());
+        }
+    }
+}
+
+// Hosts a public session and joins it, returns the matched addresses
+fn match_over(simulation: &mut Simulation) -> Option<(String, String)> {
+    let mut host = SimClient::new("10.0.1.1:5000");
+    let mut joiner = SimClient::new("10.0.1.2:5000");
+
+    host.create(false);
+
+    let mut hosted = None;
+    let mut joined = None;
+
+    while simulation.elapsed() < Duration::from_secs(10) {
+        host.update(simulation);
+        joiner.update(simulation);
+        simulation.step();
+
+        if host.take(CREATE).is_some() {
+            joiner.join("");
+        }
+
+        if let Some(mut reader) = host.take(JOIN) {
+            assert!(reader.bool());
+            hosted = Some(reader.string());
+        }
+
+        if let Some(mut reader) = joiner.take(JOIN) {
+            assert!(reader.bool());
+            joined = Some(reader.string());
+        }
+
+        if let (Some(hosted), Some(joined)) = (&hosted, &joined) {
+            return Some((hosted.clone(), joined.clone()));
+        }
+    }
+
+    None
+}
+
+#[test]
+fn match_is_made_over_a_lossy_network() {
+    for seed in 0..8 {
+        let mut simulation = simulation(seed, lossy());
+
+        let (hosted, joined) = match_over(&mut simulation)
+            .unwrap_or_else(|| panic!("no match with seed {}", seed));
+
+        assert_eq!(hosted, "10.0.1.2:5000");
+        assert_eq!(joined, "10.0.1.1:5000");
+    }
+}
+
+#[test]
+fn same_seed_gives_the_same_run() {
+    // keys, nonces and tickets are random, so only compare headers and sizes
+    let run = |seed| {
+        let mut simulation = simulation(seed, lossy());
+        match_over(&mut simulation).unwrap();
+
+        simulation.trace()
+            .iter()
+            .map(|datagram| (datagram.deliver_at, datagram.from, datagram.to, datagram.data.len(), datagram.data[..7].to_vec()))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(run(42), run(42));
+    assert_ne!(run(42), run(43));
+}
+
+#[test]
+fn silent_client_is_dropped_on_time() {
+    let mut simulation = simulation(0, NetworkConditions::default());
+    let client: SocketAddr = "10.0.1.1:5000".parse().unwrap();
+
+    simulation.send(client, &encode(0, CREATE, &{
+        let mut fields = string_u8(CLIENT_HASH);
+        fields.push(0);
+        fields
+    }));
+
+    let mut closed_at = None;
+
+    while closed_at.is_none() && simulation.elapsed() < Duration::from_secs(10) {
+        simulation.step();
+
+        while let Some(data) = simulation.recv(client) {
+            if let Reply::Data { packet: CLOSE, .. } = parse_reply(&data) {
+                closed_at = Some(simulation.elapsed());
+            }
+        }
+    }
+
+    // the close is sent on the first tick after 5s of silence and arrives on the next
+    let closed_at = closed_at.expect("client was not dropped");
+    assert!(closed_at > Duration::from_secs(5), "{:?}", closed_at);
+    assert!(closed_at <= Duration::from_millis(5050), "{:?}", closed_at);
+}
+
+#[test]
+fn shutdown_stops_the_simulation() {
+    let mut simulation = simulation(0, NetworkConditions::default());
+
+    simulation.shutdown();
+    simulation.run_for(Duration::from_secs(1));
+
+    assert!(simulation.is_stopped());
+}
+
+#[test]
+fn random_packets_keep_the_server_consistent() {
+    use rand::prng::XorShiftRng;
+    use rand::{Rng, SeedableRng};
+
+    let mut rng = XorShiftRng::seed_from_u64(7);
+    let config = ServerConfig {
+        // short keys so hosts collide on them
+        key_length: 1,
+        ..simulation_config()
+    };
+
+    let mut simulation = Simulation::new(config, 7, lossy());
+    simulation.server().support_client_hashes(vec![String::from(CLIENT_HASH)]);
+
+    let clients: Vec<SocketAddr> = (1..=4)
+        .map(|i| SocketAddr::from(([10, 0, 1, i], 5000)))
+        .collect();
+
+    for _ in 0..1000 {
+        let from = clients[rng.gen_range(0, clients.len())];
+        let id = rng.gen_range(0, 64);
+
+        let mut fields = Vec::new();
+
+        let packet = match rng.gen_range(0, 8) {
+            0 => PING_PONG,
+            1 => {
+                fields.extend(&rng.gen_range(0u32, 64).to_le_bytes());
+                ACK
+            },
+            2 => {
+                fields.extend(string_u8(CLIENT_HASH));
+                fields.push(rng.gen());
+                CREATE
+            },
+            3 => {
+                let key: String = if rng.gen() { String::new() } else { (rng.gen_range(b'0', b'9') as char).to_string() };
+                fields.extend(string_u8(CLIENT_HASH));
+                fields.extend(string_u8(&key));
+                JOIN
+            },
+            4 => CLOSE,
+            5 => {
+                fields.push(16);
+                fields.extend((0..16).map(|_| rng.gen::<u8>()));
+                RESUME
+            },
+            _ => {
+                fields.extend((0..rng.gen_range(0, 32)).map(|_| rng.gen::<u8>()));
+                rng.gen_range(0, 16)
+            }
+        };
+
+        simulation.send(from, &encode(id, packet, &fields));
+
+        for _ in 0..rng.gen_range(0, 4) {
+            simulation.step();
+        }
+
+        for client in &clients {
+            while simulation.recv(*client).is_some() {}
+        }
+
+        simulation.check_invariants().unwrap();
+    }
+}
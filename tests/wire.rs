@@ -0,0 +1,232 @@
+// Every packet survives encode then decode, in both directions
+
+use matchmaker::packets::{ClientPacket, ErrorCode, JoinReply, ServerPacket, Wire, read_str_u8, write_bytes_u8, write_string_u8};
+use proptest::prelude::*;
+use std::net::{IpAddr, SocketAddr};
+
+const ERROR_CODES: [ErrorCode; 8] = [
+    ErrorCode::Unknown,
+    ErrorCode::NotAttested,
+    ErrorCode::AttestationFailed,
+    ErrorCode::KeyExchangeFailed,
+    ErrorCode::SessionFailed,
+    ErrorCode::ShuttingDown,
+    ErrorCode::Maintenance,
+    ErrorCode::ResumeFailed
+];
+
+fn encode<'a, P: Wire<'a>>(packet: &P) -> Vec<u8> {
+    let mut buf = Vec::new();
+    packet.encode(&mut buf);
+    buf
+}
+
+fn decode<'a, P: Wire<'a>>(mut buf: &'a [u8]) -> Option<P> {
+    let packet = P::decode(&mut buf)?;
+
+    // every field is read
+    assert!(buf.is_empty(), "{} bytes left over", buf.len());
+
+    Some(packet)
+}
+
+// Strings and bytes that fit in a u8 length
+fn short_string() -> impl Strategy<Value = String> {
+    "\\PC{0,63}"
+}
+
+fn short_bytes() -> impl Strategy<Value = Vec<u8>> {
+    prop::collection::vec(any::<u8>(), 0..=255)
+}
+
+fn client_packet() -> impl Strategy<Value = ClientPacket> {
+    prop_oneof![
+        Just(ClientPacket::Pong),
+        any::<u32>().prop_map(|id| ClientPacket::Ack { id }),
+        (short_string(), any::<bool>()).prop_map(|(client_hash, password_protected)| ClientPacket::Create { client_hash, password_protected }),
+        (short_string(), short_string()).prop_map(|(client_hash, session_key)| ClientPacket::Join { client_hash, session_key }),
+        Just(ClientPacket::Close),
+        (short_string(), short_bytes()).prop_map(|(build, mac)| ClientPacket::Attest { build, mac }),
+        short_bytes().prop_map(|public_key| ClientPacket::KeyExchange { public_key }),
+        short_bytes().prop_map(|token| ClientPacket::Resume { token }),
+        prop::collection::vec(any::<u8>(), 0..1024).prop_map(|data| ClientPacket::Sealed { data })
+    ]
+}
+
+// Server packets borrow their fields, so the strategy picks a variant and
+// owned fields for the test to build it from
+#[derive(Clone, Debug)]
+struct ServerFields {
+    variant: u8,
+    id: u32,
+    flag: bool,
+    code: ErrorCode,
+    address: SocketAddr,
+    text: String,
+    bytes: Vec<u8>,
+    more_bytes: Vec<u8>
+}
+
+fn server_fields() -> impl Strategy<Value = ServerFields> {
+    (
+        0..12u8,
+        any::<u32>(),
+        any::<bool>(),
+        prop::sample::select(&ERROR_CODES[..]),
+        // addresses are sent as text, which has no IPv6 flow info
+        (any::<IpAddr>(), any::<u16>()).prop_map(SocketAddr::from),
+        short_string(),
+        short_bytes(),
+        short_bytes()
+    ).prop_map(|(variant, id, flag, code, address, text, bytes, more_bytes)| ServerFields {
+        variant, id, flag, code, address, text, bytes, more_bytes
+    })
+}
+
+fn server_packet(fields: &ServerFields) -> ServerPacket<'_> {
+    match fields.variant {
+        0 => ServerPacket::Ping,
+        1 => ServerPacket::Ack { id: fields.id },
+        2 => ServerPacket::Create { session_key: &fields.text },
+        3 if fields.flag => ServerPacket::Join(Some(JoinReply {
+            client_addr: fields.address,
+            ticket: &fields.bytes,
+            match_key: &fields.more_bytes
+        })),
+        3 => ServerPacket::Join(None),
+        4 => ServerPacket::Close { reason: &fields.text },
+        5 => ServerPacket::Error { id: fields.id, code: fields.code, message: &fields.text },
+        6 => ServerPacket::Challenge { nonce: &fields.bytes },
+        7 => ServerPacket::Attest { success: fields.flag },
+        8 => ServerPacket::KeyExchange { public_key: &fields.bytes, signature: &fields.more_bytes },
+        9 => ServerPacket::Notice { message: &fields.text },
+        10 => ServerPacket::Resume { token: &fields.bytes },
+        _ => ServerPacket::SessionExpired { session_key: &fields.text }
+    }
+}
+
+proptest! {
+    #[test]
+    fn client_packets_round_trip(packet in client_packet()) {
+        prop_assert_eq!(decode::<ClientPacket>(&encode(&packet)), Some(packet));
+    }
+
+    #[test]
+    fn server_packets_round_trip(fields in server_fields()) {
+        let packet = server_packet(&fields);
+        let encoded = encode(&packet);
+
+        prop_assert_eq!(decode::<ServerPacket>(&encoded), Some(packet));
+    }
+
+    // Whatever the parser accepts encodes back to the same packet
+    #[test]
+    fn decoded_client_packets_are_canonical(data in prop::collection::vec(any::<u8>(), 0..64)) {
+        let mut buf = data.as_slice();
+
+        if let Some(packet) = ClientPacket::decode(&mut buf) {
+            prop_assert_eq!(decode::<ClientPacket>(&encode(&packet)), Some(packet));
+        }
+    }
+
+    #[test]
+    fn decoded_server_packets_are_canonical(data in prop::collection::vec(any::<u8>(), 0..64)) {
+        let mut buf = data.as_slice();
+
+        if let Some(packet) = ServerPacket::decode(&mut buf) {
+            let encoded = encode(&packet);
+            prop_assert_eq!(decode::<ServerPacket>(&encoded), Some(packet));
+        }
+    }
+
+    #[test]
+    fn truncated_packets_are_rejected(fields in server_fields(), cut in any::<prop::sample::Index>()) {
+        let encoded = encode(&server_packet(&fields));
+        let len = cut.index(encoded.len());
+
+        // older servers send errors without the trailing code
+        prop_assume!(fields.variant != 5 || len < encoded.len() - 2);
+
+        let mut buf = &encoded[..len];
+        prop_assert!(ServerPacket::decode(&mut buf).is_none());
+    }
+
+    // Long strings are cut to the longest whole-character prefix that fits
+    #[test]
+    fn long_strings_are_truncated(text in "\\PC{0,300}") {
+        let mut buf = Vec::new();
+        write_string_u8(&mut buf, &text);
+
+        let decoded = read_str_u8(&mut buf.as_slice()).unwrap();
+
+        prop_assert!(decoded.len() <= 255);
+        prop_assert!(text.starts_with(decoded));
+
+        if text.len() > 255 {
+            let next = text[decoded.len()..].chars().next().unwrap();
+            prop_assert!(decoded.len() + next.len_utf8() > 255);
+        } else {
+            prop_assert_eq!(decoded, text.as_str());
+        }
+    }
+
+    #[test]
+    fn long_bytes_are_truncated(bytes in prop::collection::vec(any::<u8>(), 0..600)) {
+        let mut buf = Vec::new();
+        write_bytes_u8(&mut buf, &bytes);
+
+        let len = bytes.len().min(255);
+        prop_assert_eq!(buf[0] as usize, len);
+        prop_assert_eq!(&buf[1..], &bytes[..len]);
+    }
+}
+
+#[test]
+fn strings_are_not_cut_inside_a_character() {
+    // 256 bytes of two byte characters
+    let text = "é".repeat(128);
+
+    let mut buf = Vec::new();
+    write_string_u8(&mut buf, &text);
+
+    assert_eq!(buf[0], 254);
+    assert_eq!(read_str_u8(&mut buf.as_slice()), Some("é".repeat(127).as_str()));
+}
+
+#[test]
+fn strings_of_255_bytes_are_kept() {
+    let text = "a".repeat(255);
+
+    let mut buf = Vec::new();
+    write_string_u8(&mut buf, &text);
+
+    assert_eq!(read_str_u8(&mut buf.as_slice()), Some(text.as_str()));
+}
+
+#[test]
+fn errors_from_older_servers_have_no_code() {
+    // `[packet u16][id u32][message]` without the trailing code
+    let mut buf = vec![5, 0, 7, 0, 0, 0];
+    write_string_u8(&mut buf, "Session failed to create");
+
+    assert_eq!(
+        decode::<ServerPacket>(&buf),
+        Some(ServerPacket::Error { id: 7, code: ErrorCode::Unknown, message: "Session failed to create" })
+    );
+}
+
+#[test]
+fn successful_joins_need_the_peer_address() {
+    // `[packet u16][success bool][address str][ticket bytes][match key bytes]`
+    for address in &["", "not an address"] {
+        let mut buf = vec![3, 0, 1];
+        write_string_u8(&mut buf, address);
+        write_bytes_u8(&mut buf, &[7; 8]);
+        write_bytes_u8(&mut buf, &[9; 32]);
+
+        assert_eq!(ServerPacket::decode(&mut buf.as_slice()), None, "address {:?}", address);
+    }
+
+    // a failed join is nothing but the flag
+    assert_eq!(decode::<ServerPacket>(&[3, 0, 0]), Some(ServerPacket::Join(None)));
+}